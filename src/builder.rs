@@ -0,0 +1,1190 @@
+use std::collections::{hash_map, HashMap};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use atom_syndication::{Category as EntryCategory, Entry, Feed, Generator, Link, Person};
+use chrono::{FixedOffset, Utc};
+use url::Url;
+
+use crate::category::{collect_articles, Article, ArticleFiles, Category, ROOT_CATEGORY};
+use crate::content::{truncate, ContentOptions, ContentType};
+use crate::date::{remove_rfc3339_date, Dater, SortBy, TimeSource};
+use crate::error::{Diagnostic, Diagnostics, GemAtomError, Result};
+use crate::exclude::{Exclude, Excluder};
+use crate::gemtext::Document;
+use crate::id::IdScheme;
+use crate::meta::Metadata;
+use crate::state::{hash, Record, State, STATE_FILE};
+use crate::util::{extract_title, find_index, path_str, read_gemtext};
+use crate::VERSION;
+
+/// Builds an atom feed out of the categories of a gemini site.
+///
+/// The site is described by its root directory and its base URL.
+/// Everything else has a sensible default.
+#[derive(Clone, Debug)]
+pub struct FeedBuilder {
+    root: PathBuf,
+    base_url: Url,
+    categories: HashMap<String, Category>,
+    files: HashMap<String, ArticleFiles>,
+    ignored: Vec<PathBuf>,
+    excludes: Vec<Exclude>,
+    time_source: TimeSource,
+    sort_by: SortBy,
+    id_scheme: IdScheme,
+    tag_authority: Option<String>,
+    timezone: FixedOffset,
+    max_entries: usize,
+    output: String,
+    title: Option<String>,
+    subtitle: Option<String>,
+    author: Option<String>,
+    email: Option<String>,
+    clean_title: bool,
+    content: ContentOptions,
+    strict: bool,
+    per_category: bool,
+    incremental: bool,
+    dry_run: bool,
+    verbose: bool,
+}
+
+/// An article with its record and, when the content is wanted, its
+/// text as read for the record, without the metadata block.
+type Recorded = (Article, Record, Option<String>);
+
+/// State shared by the feeds built at once.
+struct Run {
+    dater: Dater,
+    /// Records of the previous run, when kept.
+    old: State,
+    /// Records of this run.
+    state: State,
+    /// Entries already read, indexed by article number.
+    cache: HashMap<usize, Option<Entry>>,
+    diags: Diagnostics,
+}
+
+/// A feed built by [`FeedBuilder::build_all`].
+#[derive(Clone, Debug)]
+pub struct GeneratedFeed {
+    /// Category of the feed, `None` for the site-wide feed.
+    pub category: Option<String>,
+    /// Path of the feed file, relative to the site root.
+    pub path: PathBuf,
+    /// URL the feed file is served from.
+    pub url: Url,
+    /// The feed itself.
+    pub feed: Feed,
+}
+
+impl FeedBuilder {
+    /// Create a builder for the site rooted at `root` and served from
+    /// `base_url`.
+    pub fn new<P: AsRef<Path>>(root: P, base_url: Url) -> Self {
+        FeedBuilder {
+            root: root.as_ref().to_path_buf(),
+            base_url,
+            categories: HashMap::new(),
+            files: HashMap::new(),
+            ignored: Vec::new(),
+            excludes: Vec::new(),
+            time_source: TimeSource::Ctime,
+            sort_by: SortBy::Published,
+            id_scheme: IdScheme::Url,
+            tag_authority: None,
+            timezone: FixedOffset::east(0),
+            max_entries: 10,
+            output: String::from("atom.xml"),
+            title: None,
+            subtitle: None,
+            author: None,
+            email: None,
+            clean_title: false,
+            content: ContentOptions::default(),
+            strict: false,
+            per_category: false,
+            incremental: false,
+            dry_run: false,
+            verbose: false,
+        }
+    }
+
+    /// Add a category, i.e. a subdirectory of the root directory, or
+    /// the root directory itself for the `"."` pseudo-category, whose
+    /// index file is not an article either.
+    pub fn category<S: Into<String>>(mut self, name: S, typ: Category) -> Self {
+        self.categories.insert(name.into(), typ);
+        self
+    }
+
+    /// Add several categories at once.
+    pub fn categories<I, S>(mut self, categories: I) -> Self
+    where
+        I: IntoIterator<Item = (S, Category)>,
+        S: Into<String>,
+    {
+        for (name, typ) in categories {
+            self.categories.insert(name.into(), typ);
+        }
+        self
+    }
+
+    /// Tell which files of category `name` are articles (default
+    /// [`ArticleFiles::default`]).
+    pub fn category_files<S: Into<String>>(mut self, name: S, files: ArticleFiles) -> Self {
+        self.files.insert(name.into(), files);
+        self
+    }
+
+    /// Never consider the file at `path`, relative to the root
+    /// directory, as an article, e.g. because it is generated.
+    pub fn ignore_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.ignored.push(path.into());
+        self
+    }
+
+    /// Leave out the articles whose path relative to the root matches
+    /// `pattern`.
+    pub fn exclude(mut self, pattern: Exclude) -> Self {
+        self.excludes.push(pattern);
+        self
+    }
+
+    /// Time source used for the publication date of undated articles
+    /// (default [`TimeSource::Ctime`]). Update dates come from the git
+    /// history with [`TimeSource::Git`], from modification times
+    /// otherwise.
+    pub fn time_source(mut self, time_source: TimeSource) -> Self {
+        self.time_source = time_source;
+        self
+    }
+
+    /// Date ordering the entries (default [`SortBy::Published`]).
+    pub fn sort_by(mut self, sort_by: SortBy) -> Self {
+        self.sort_by = sort_by;
+        self
+    }
+
+    /// How the ids of new entries are made (default
+    /// [`IdScheme::Url`]). Tag ids are kept in the state file, even
+    /// when not [`incremental`](#method.incremental).
+    pub fn id_scheme(mut self, id_scheme: IdScheme) -> Self {
+        self.id_scheme = id_scheme;
+        self
+    }
+
+    /// Authority minting the tag ids, a domain name or an email
+    /// address followed by a date at which it was owned, such as
+    /// `example.org,2021` (default the host of the base URL on the
+    /// date of each article).
+    pub fn tag_authority<S: Into<String>>(mut self, authority: S) -> Self {
+        self.tag_authority = Some(authority.into());
+        self
+    }
+
+    /// Timezone of the dates given without offset, in file names or
+    /// metadata (default UTC).
+    pub fn timezone(mut self, timezone: FixedOffset) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Keep at most `n` entries in the feed (default 10).
+    pub fn max_entries(mut self, n: usize) -> Self {
+        self.max_entries = n;
+        self
+    }
+
+    /// Name of the feed file, relative to the base URL (default
+    /// `atom.xml`).
+    pub fn output<S: Into<String>>(mut self, output: S) -> Self {
+        self.output = output.into();
+        self
+    }
+
+    /// Feed title. When not set, it is taken from the root index file.
+    pub fn title<S: Into<String>>(mut self, title: S) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Feed subtitle.
+    pub fn subtitle<S: Into<String>>(mut self, subtitle: S) -> Self {
+        self.subtitle = Some(subtitle.into());
+        self
+    }
+
+    /// Feed author name.
+    pub fn author<S: Into<String>>(mut self, author: S) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    /// Feed author email.
+    pub fn email<S: Into<String>>(mut self, email: S) -> Self {
+        self.email = Some(email.into());
+        self
+    }
+
+    /// When using a file or directory name as a title, convert '_'
+    /// into space.
+    pub fn clean_title(mut self, clean: bool) -> Self {
+        self.clean_title = clean;
+        self
+    }
+
+    /// Include the first `paragraphs` paragraphs following the first
+    /// heading of each article as the entry summary (0, the default,
+    /// for no summary).
+    pub fn summary(mut self, paragraphs: usize) -> Self {
+        self.content.summary = paragraphs;
+        self
+    }
+
+    /// Include the whole article as the entry content, with the given
+    /// type (default `None`, no content).
+    pub fn content(mut self, content: Option<ContentType>) -> Self {
+        self.content.content = content;
+        self
+    }
+
+    /// Truncate summaries and contents to `max` characters.
+    pub fn max_length(mut self, max: Option<usize>) -> Self {
+        self.content.max_length = max;
+        self
+    }
+
+    /// Abort on the first unusable article instead of skipping it.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Also build one feed per category, written in the category
+    /// directory, see [`build_all`](#method.build_all).
+    pub fn per_category(mut self, per_category: bool) -> Self {
+        self.per_category = per_category;
+        self
+    }
+
+    /// Keep what is known of every article in a state file in the
+    /// root directory, and only read again the articles which changed
+    /// since the previous run.
+    pub fn incremental(mut self, incremental: bool) -> Self {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Leave the state file of [`incremental`](#method.incremental)
+    /// runs untouched, e.g. to only compare the feeds with the
+    /// written ones.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Report progress on stdout.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Build the feed.
+    ///
+    /// Unless in strict mode, unusable articles are silently skipped.
+    /// Use [`build_with_diagnostics`](#method.build_with_diagnostics)
+    /// to know which ones.
+    pub fn build(&self) -> Result<Feed> {
+        self.build_with_diagnostics().map(|(feed, _)| feed)
+    }
+
+    /// Build the feed, also returning the articles which were skipped
+    /// because of an error.
+    pub fn build_with_diagnostics(&self) -> Result<(Feed, Vec<Diagnostic>)> {
+        let (mut feeds, skipped) = self.build_feeds(false)?;
+        Ok((feeds.remove(0).feed, skipped))
+    }
+
+    /// Build the site-wide feed, followed by one feed per category if
+    /// [`per_category`](#method.per_category) is set. Articles are
+    /// collected and read only once for all feeds. Also returns the
+    /// articles which were skipped because of an error.
+    pub fn build_all(&self) -> Result<(Vec<GeneratedFeed>, Vec<Diagnostic>)> {
+        self.build_feeds(self.per_category)
+    }
+
+    /// Paths of the feeds built by [`build_all`](#method.build_all),
+    /// relative to the root directory, the site-wide feed first.
+    pub fn feed_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![PathBuf::from(&self.output)];
+        if self.per_category {
+            for name in self.feed_categories() {
+                paths.push(Path::new(name).join(&self.output));
+            }
+        }
+        paths
+    }
+
+    /// Categories having their own feed, sorted by name. The feed of
+    /// the root directory is the site feed.
+    fn feed_categories(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self
+            .categories
+            .keys()
+            .filter(|name| name.as_str() != ROOT_CATEGORY)
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn build_feeds(&self, per_category: bool) -> Result<(Vec<GeneratedFeed>, Vec<Diagnostic>)> {
+        let state_path = self.root.join(STATE_FILE);
+        let options = self.options();
+        let mut run = Run {
+            dater: Dater::new(self.time_source, self.timezone, &self.root),
+            old: if self.keeps_state() {
+                State::load(&state_path, options.clone())
+            } else {
+                State::new(options.clone())
+            },
+            state: State::new(options),
+            cache: HashMap::new(),
+            diags: Diagnostics::new(self.strict),
+        };
+        let directory = path_str(&self.root)?;
+        let files = self.get_files(&mut run)?;
+        if files.is_empty() && self.verbose {
+            println!("No world-readable gemini content found! :(");
+        }
+
+        let title = match &self.title {
+            Some(t) => t.clone(),
+            None => get_feed_title(directory, self.clean_title, self.files_of(ROOT_CATEGORY))?,
+        };
+        let site_feed = self.build_one(
+            title,
+            &self.base_url,
+            &self.output,
+            files.iter().enumerate(),
+            &mut run,
+        )?;
+        let mut feeds = vec![GeneratedFeed {
+            category: None,
+            path: PathBuf::from(&self.output),
+            url: join_url(&self.base_url, &self.output)?,
+            feed: site_feed,
+        }];
+
+        if per_category {
+            for name in self.feed_categories() {
+                let cat_dir = self.root.join(name);
+                let title =
+                    get_feed_title(path_str(&cat_dir)?, self.clean_title, self.files_of(name))?;
+                let cat_url = join_url(&self.base_url, &format!("{}/", name))?;
+                let feed = self.build_one(
+                    title,
+                    &cat_url,
+                    &self.output,
+                    files
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, (a, _, _))| &a.category == name),
+                    &mut run,
+                )?;
+                feeds.push(GeneratedFeed {
+                    category: Some(name.clone()),
+                    path: Path::new(name).join(&self.output),
+                    url: join_url(&cat_url, &self.output)?,
+                    feed,
+                });
+            }
+        }
+        if self.keeps_state() && !self.dry_run {
+            run.state.keep_ids(&run.old, &self.root);
+            run.state.save(&state_path)?;
+        }
+        Ok((feeds, run.diags.skipped))
+    }
+
+    /// Key of the article at `filepath` in the state: its path
+    /// relative to the root directory, however the root is spelled.
+    fn state_key<'a>(&self, filepath: &'a str) -> &'a str {
+        Path::new(filepath)
+            .strip_prefix(&self.root)
+            .ok()
+            .and_then(Path::to_str)
+            .unwrap_or(filepath)
+    }
+
+    /// Whether the state file is read and written: when incremental,
+    /// or to remember the tag ids.
+    fn keeps_state(&self) -> bool {
+        self.incremental || self.id_scheme == IdScheme::Tag
+    }
+
+    /// The options an article record depends on. Records saved with
+    /// other options are not reused.
+    fn options(&self) -> String {
+        let mut categories: Vec<_> = self.categories.iter().collect();
+        categories.sort_by_key(|(name, _)| *name);
+        format!(
+            "{} {:?} {:?} {:?} {} {} {:?} {:?}",
+            VERSION,
+            categories,
+            self.id_scheme,
+            self.time_source,
+            self.timezone,
+            self.clean_title,
+            self.content.summary,
+            self.content.max_length
+        )
+    }
+
+    /// Build a feed served under `url`, whose entries are the first
+    /// usable `articles`. Entries already read are kept in the cache
+    /// of `run`.
+    fn build_one<'a, I>(
+        &self,
+        title: String,
+        url: &Url,
+        output: &str,
+        articles: I,
+        run: &mut Run,
+    ) -> Result<Feed>
+    where
+        I: Iterator<Item = (usize, &'a Recorded)>,
+    {
+        let feed_url = join_url(url, output)?;
+        if self.verbose {
+            println!(
+                "Generating feed \"{}\", which should be served from {}",
+                title, feed_url
+            );
+        }
+        let mut feed = Feed::default();
+        feed.set_id(url.as_str());
+        feed.set_title(title);
+        feed.set_subtitle(self.subtitle.clone());
+        let mut gen = Generator::default();
+        gen.set_value("gematom, an atom feed generator for gemini.");
+        gen.set_uri("https://github.com/drtutut/gematom".to_string());
+        gen.set_version(VERSION.to_string());
+        feed.set_generator(gen);
+        feed.set_rights("© Éric Würbel 2021".to_string());
+        let mut person = Person::default();
+        if let Some(a) = &self.author {
+            person.set_name(a.as_str());
+        }
+        person.set_email(self.email.clone());
+        if !person.name.is_empty() || person.email.is_some() {
+            let v = vec![person];
+            feed.set_authors(v);
+        }
+        let mut self_link = Link::default();
+        let mut alt_link = Link::default();
+        self_link.set_href(url.as_str());
+        self_link.set_rel("self");
+        alt_link.set_href(url.as_str());
+        alt_link.set_rel("alternate");
+        let v = vec![self_link, alt_link];
+        feed.set_links(v);
+
+        let mut entries = Vec::new();
+        for (idx, (article, record, body)) in articles {
+            if entries.len() == self.max_entries {
+                break;
+            }
+            if let hash_map::Entry::Vacant(slot) = run.cache.entry(idx) {
+                let entry = match self.populate_entry(&article.path, record, body.as_deref()) {
+                    Ok(entry) => Some(entry),
+                    Err(e) => {
+                        run.diags.skip(&article.path, e)?;
+                        None
+                    }
+                };
+                slot.insert(entry);
+            }
+            if let Some(Some(entry)) = run.cache.get(&idx) {
+                if self.verbose {
+                    println!("Adding {} with title {}", &article.path, entry.title());
+                }
+                entries.push(entry.clone())
+            }
+        }
+        if !entries.is_empty() {
+            if let Some(updated) = entries.iter().map(|e| *e.updated()).max() {
+                feed.set_updated(updated);
+            }
+            feed.set_entries(entries);
+        }
+        Ok(feed)
+    }
+
+    /// Extract the files in the categories, starting from the root
+    /// directory, leaving out ignored and excluded files, drafts, and
+    /// articles published in the future. Files are read into their
+    /// records, which are kept in the state of `run`. They are sorted
+    /// by their `sort_by` date, most recent first, then by path.
+    /// Unusable files are recorded in the diagnostics of `run`.
+    fn get_files(&self, run: &mut Run) -> Result<Vec<Recorded>> {
+        let root = path_str(&self.root)?;
+        let mut excluder = Excluder::new(Path::new(root), &self.excludes);
+        let now = Utc::now();
+        let mut files = Vec::new();
+        for (cat, typ) in &self.categories {
+            let cat_files = self.files_of(cat);
+            for article in collect_articles(cat, *typ, cat_files, root, &mut run.diags)? {
+                let path = Path::new(&article.path);
+                let rel = path.strip_prefix(&self.root).unwrap_or(path);
+                if self.ignored.iter().any(|i| i == rel) {
+                    continue;
+                }
+                match excluder.is_excluded(path) {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        if self.verbose {
+                            println!("Excluding {}", &article.path);
+                        }
+                        continue;
+                    }
+                    Err(e) => {
+                        run.diags.skip(&article.path, e)?;
+                        continue;
+                    }
+                }
+                match self.read_record(&run.dater, &run.old, &article) {
+                    // unpublished articles are kept out of the state,
+                    // which lies in the served root directory
+                    Ok((record, body)) => match record.published {
+                        _ if record.draft => {}
+                        Some(p) if p.with_timezone(&Utc) > now => {
+                            if self.verbose {
+                                println!("Scheduling {} for {}", &article.path, p.to_rfc3339());
+                            }
+                        }
+                        _ => {
+                            let key = self.state_key(&article.path);
+                            run.state.insert(key.to_string(), record.clone());
+                            files.push((article, record, body));
+                        }
+                    },
+                    Err(e) => run.diags.skip(&article.path, e)?,
+                }
+            }
+        }
+        let date = |record: &Record| match self.sort_by {
+            SortBy::Published => record.published.unwrap_or(record.updated),
+            SortBy::Updated => record.updated,
+        };
+        // categories come in any order, ties are broken by path
+        files.sort_by(|(a, ra, _), (b, rb, _)| {
+            date(rb).cmp(&date(ra)).then_with(|| a.path.cmp(&b.path))
+        });
+        Ok(files)
+    }
+
+    /// Which files of category `name` are articles.
+    fn files_of(&self, name: &str) -> &ArticleFiles {
+        lazy_static! {
+            static ref DEFAULT: ArticleFiles = ArticleFiles::default();
+        }
+        self.files.get(name).unwrap_or(&DEFAULT)
+    }
+
+    /// Get the record of `article`. When incremental, the record of
+    /// the previous run is kept if the file has the same size and
+    /// modification time, or the same contents, in which case its
+    /// dates do not move. Otherwise the file is read and dated by
+    /// `dater`. Its metadata take precedence over the title and dates
+    /// guessed from it. When the content is wanted, the file is always
+    /// read, and its text is returned along the record.
+    fn read_record(
+        &self,
+        dater: &Dater,
+        old: &State,
+        article: &Article,
+    ) -> Result<(Record, Option<String>)> {
+        let filepath = article.path.as_str();
+        let stat = fs::metadata(filepath).map_err(|e| GemAtomError::io(filepath, e))?;
+        let key = self.state_key(filepath);
+        let previous = old.get(key);
+        let reused = previous.filter(|_| self.incremental);
+        let wanted = self.content.content.is_some();
+        if let Some(record) = reused.filter(|r| !wanted && r.is_fresh(&stat)) {
+            if self.verbose {
+                println!("Keeping {} from the previous run", filepath);
+            }
+            return Ok((record.clone(), None));
+        }
+        let text = read_gemtext(filepath)?;
+        let digest = hash(text.as_bytes());
+        let doc = Document::parse(&text);
+        let (meta, body) = Metadata::split(&doc);
+        // the content is taken from this very text, not read again
+        let kept = if wanted {
+            Some(body.text().to_string())
+        } else {
+            None
+        };
+        if let Some(record) = reused.filter(|r| r.hash == digest) {
+            if self.verbose {
+                println!("Keeping {} from the previous run", filepath);
+            }
+            let mut record = record.clone();
+            record.touch(&stat);
+            return Ok((record, kept));
+        }
+        let published = dater.publish_time(filepath, &meta, article.typ)?;
+        let (updated, origin) = dater.update_time(filepath, &meta, published)?;
+        if self.verbose && !meta.draft {
+            println!("Dating {} by its {}", filepath, origin);
+        }
+        let id = match (
+            meta.id,
+            previous.or_else(|| old.renamed(&self.root, &digest)),
+        ) {
+            (Some(id), _) => id,
+            (None, Some(record)) => record.id.clone(),
+            (None, None) => match old.id(key) {
+                Some(id) => id.to_string(),
+                None => self.id_scheme.id(
+                    self.tag_authority.as_deref(),
+                    &self.entry_url(filepath)?,
+                    &published.unwrap_or(updated),
+                ),
+            },
+        };
+        let pfile = Path::new(filepath);
+        let file_name = match pfile.file_name() {
+            Some(name) => path_str(Path::new(name))?,
+            None => filepath,
+        };
+        let named = match article.typ {
+            Category::Flat | Category::Dated => pfile.file_stem(),
+            Category::Tree => pfile.parent().and_then(|p| p.file_name()),
+        };
+        let default_title = remove_rfc3339_date(match named {
+            Some(n) => path_str(Path::new(n))?,
+            None => file_name,
+        });
+        let default_title = if self.clean_title {
+            default_title.replace("_", " ")
+        } else {
+            default_title.to_string()
+        };
+        let title = match meta.title {
+            Some(t) => t,
+            None => body.first_heading().unwrap_or(&default_title).to_string(),
+        };
+        let summary = match &meta.summary {
+            Some(s) => Some(truncate(s, self.content.max_length)),
+            None => self.content.summary(&body),
+        };
+        let record = Record {
+            id,
+            size: stat.len(),
+            mtime: stat.mtime(),
+            mtime_nsec: stat.mtime_nsec(),
+            hash: digest,
+            title,
+            published,
+            updated,
+            origin: origin.to_string(),
+            draft: meta.draft,
+            author: meta.author,
+            tags: meta.tags,
+            summary,
+        };
+        Ok((record, kept))
+    }
+
+    /// URL of the Gemini file at `filepath`, under the base URL.
+    fn entry_url(&self, filepath: &str) -> Result<Url> {
+        let root = path_str(&self.root)?;
+        let pfile = Path::new(filepath);
+        let file_name = match pfile.file_name() {
+            Some(name) => path_str(Path::new(name))?,
+            None => filepath,
+        };
+        if pfile.parent() == Some(Path::new(root)) {
+            join_url(&self.base_url, file_name)
+        } else {
+            join_url(&self.base_url, &filepath[root.len()..])
+        }
+    }
+
+    /// Build the entry of the Gemini file at `filepath` out of its
+    /// `record`, with its URL under the base URL. Its content, when
+    /// wanted, is made of `body`, the text read for the record.
+    fn populate_entry(&self, filepath: &str, record: &Record, body: Option<&str>) -> Result<Entry> {
+        let url = self.entry_url(filepath)?;
+        let mut entry = Entry::default();
+        entry.set_id(record.id.as_str());
+        let mut link = Link::default();
+        link.set_href(url.as_str());
+        link.set_rel("alternate");
+        entry.set_links(vec![link]);
+        entry.set_updated(record.updated);
+        entry.set_published(record.published);
+        entry.set_title(record.title.as_str());
+        if let Some(a) = &record.author {
+            let mut person = Person::default();
+            person.set_name(a.as_str());
+            entry.set_authors(vec![person]);
+        }
+        let categories: Vec<_> = record
+            .tags
+            .iter()
+            .map(|t| {
+                let mut c = EntryCategory::default();
+                c.set_term(t.as_str());
+                c
+            })
+            .collect();
+        entry.set_categories(categories);
+        entry.set_summary(record.summary.clone());
+        if let Some(body) = body {
+            entry.set_content(self.content.content(&Document::parse(body), &url));
+        }
+        Ok(entry)
+    }
+}
+
+/// Join `path` to the `base` URL.
+fn join_url(base: &Url, path: &str) -> Result<Url> {
+    base.join(path).map_err(|e| GemAtomError::UrlJoin {
+        base: base.to_string(),
+        path: path.to_string(),
+        source: e,
+    })
+}
+
+/// Get the feed title.
+///
+/// If there is an index file named as in `files`, try to extract the
+/// first heading, otherwise use the directory name.
+fn get_feed_title(dir: &str, clean: bool, files: &ArticleFiles) -> Result<String> {
+    let d = Path::new(dir);
+    let default = match d.file_name() {
+        Some(name) => path_str(Path::new(name))?,
+        None => dir,
+    };
+    let default = if clean {
+        default.replace("_", " ")
+    } else {
+        default.to_string()
+    };
+    match find_index(d, &files.index) {
+        Some(index_path) => extract_title(path_str(&index_path)?, &default),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Format;
+    use crate::util::{is_world_readable, TempDir};
+    use atom_syndication::FixedDateTime;
+    use std::fs;
+
+    /// Create a small site in a fresh temporary directory.
+    fn make_site(name: &str) -> TempDir {
+        let root = TempDir::new(name);
+        fs::create_dir_all(root.join("texts")).unwrap();
+        fs::create_dir_all(root.join("noise/2021-02-01-spam")).unwrap();
+        fs::write(root.join("index.gmi"), "# My capsule\n").unwrap();
+        fs::write(root.join("texts/index.gmi"), "# Texts\n").unwrap();
+        fs::write(root.join("texts/2021-01-15-foo.gmi"), "# Foo\n").unwrap();
+        fs::write(root.join("noise/2021-02-01-spam/index.gmi"), "eggs\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn test_build_all_per_category() {
+        let root = make_site("per-category");
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let (feeds, skipped) = FeedBuilder::new(&root, base)
+            .category("texts", Category::Flat)
+            .category("noise", Category::Tree)
+            .time_source(TimeSource::Mtime)
+            .per_category(true)
+            .build_all()
+            .unwrap();
+        assert!(skipped.is_empty());
+        let summary: Vec<(Option<&str>, &Path, &str, usize)> = feeds
+            .iter()
+            .map(|f| {
+                (
+                    f.category.as_deref(),
+                    f.path.as_path(),
+                    f.feed.title(),
+                    f.feed.entries().len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (None, Path::new("atom.xml"), "My capsule", 2),
+                (Some("noise"), Path::new("noise/atom.xml"), "noise", 1),
+                (Some("texts"), Path::new("texts/atom.xml"), "Texts", 1),
+            ]
+        );
+        assert_eq!(feeds[2].feed.id(), "gemini://example.org/texts/");
+    }
+
+    #[test]
+    fn test_build_with_metadata() {
+        let root = make_site("metadata");
+        fs::write(
+            root.join("texts/bar.gmi"),
+            "title: Bar\npublished: 2020-05-06\ntags: spam, eggs\n\n# Ignored\n",
+        )
+        .unwrap();
+        fs::write(root.join("texts/baz.gmi"), "draft: true\n\n# Baz\n").unwrap();
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let feed = FeedBuilder::new(&root, base)
+            .category("texts", Category::Flat)
+            .content(Some(ContentType::Gemini))
+            .build()
+            .unwrap();
+        let titles: Vec<&str> = feed.entries().iter().map(|e| e.title()).collect();
+        assert!(titles.contains(&"Bar"));
+        assert!(!titles.contains(&"Baz"));
+        let bar = feed.entries().iter().find(|e| e.title() == "Bar").unwrap();
+        assert_eq!(
+            bar.published().map(|d| d.to_rfc3339()),
+            Some(String::from("2020-05-06T00:00:00+00:00"))
+        );
+        assert!(bar.updated() > bar.published().unwrap());
+        assert_eq!(bar.categories().len(), 2);
+        assert_eq!(bar.content().unwrap().value(), Some("# Ignored\n"));
+    }
+
+    #[test]
+    fn test_build_sort_by() {
+        let root = make_site("sort-by");
+        fs::create_dir_all(root.join("log")).unwrap();
+        fs::write(
+            root.join("log/old.gmi"),
+            "published: 2020-01-01\nupdated: 2021-06-01\n\n# Old\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("log/new.gmi"),
+            "published: 2021-01-01\nupdated: 2021-02-01\n\n# New\n",
+        )
+        .unwrap();
+        let build = |sort_by| {
+            FeedBuilder::new(&root, Url::parse("gemini://example.org/").unwrap())
+                .category("log", Category::Flat)
+                .sort_by(sort_by)
+                .build()
+                .unwrap()
+        };
+        let titles = |feed: &Feed| -> Vec<String> {
+            feed.entries()
+                .iter()
+                .map(|e| e.title().to_string())
+                .collect()
+        };
+        let by_published = build(SortBy::Published);
+        assert_eq!(titles(&by_published), ["New", "Old"]);
+        let by_updated = build(SortBy::Updated);
+        assert_eq!(titles(&by_updated), ["Old", "New"]);
+        for feed in &[by_published, by_updated] {
+            assert_eq!(feed.updated().to_rfc3339(), "2021-06-01T00:00:00+00:00");
+        }
+    }
+
+    #[test]
+    fn test_build_edited() {
+        let root = make_site("edited");
+        let foo = root.join("texts/2021-01-15-foo.gmi");
+        let edit = |text: &str, date: &str| {
+            fs::write(&foo, text).unwrap();
+            let mtime = FixedDateTime::parse_from_rfc3339(date).unwrap();
+            let file = fs::File::options().write(true).open(&foo).unwrap();
+            file.set_modified(mtime.into()).unwrap();
+        };
+        // with the default time source
+        let builder = FeedBuilder::new(&root, Url::parse("gemini://example.org/").unwrap())
+            .category("texts", Category::Flat);
+        let dates = || {
+            let feed = builder.build().unwrap();
+            let entry = &feed.entries()[0];
+            (
+                entry.published().map(|d| d.to_rfc3339()),
+                entry.updated().to_rfc3339(),
+            )
+        };
+        edit("# Foo\n", "2021-06-01T00:00:00Z");
+        let published = Some(String::from("2021-01-15T00:00:00+00:00"));
+        assert_eq!(
+            dates(),
+            (published.clone(), String::from("2021-06-01T00:00:00+00:00"))
+        );
+        edit("# Foo\n\nEdited.\n", "2021-07-01T00:00:00Z");
+        assert_eq!(
+            dates(),
+            (published, String::from("2021-07-01T00:00:00+00:00"))
+        );
+    }
+
+    #[test]
+    fn test_build_excluded() {
+        let root = make_site("excluded");
+        fs::write(root.join("texts/old-bar.gmi"), "# Bar\n").unwrap();
+        fs::write(root.join("texts/2999-01-01-later.gmi"), "# Later\n").unwrap();
+        fs::write(root.join("noise/.gematomignore"), "2021-*/\n").unwrap();
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let feed = FeedBuilder::new(&root, base)
+            .category("texts", Category::Flat)
+            .category("noise", Category::Tree)
+            .exclude("texts/old-*".parse().unwrap())
+            .build()
+            .unwrap();
+        let titles: Vec<&str> = feed.entries().iter().map(|e| e.title()).collect();
+        assert_eq!(titles, vec!["Foo"]);
+    }
+
+    #[test]
+    fn test_build_category_files() {
+        let root = make_site("category-files");
+        fs::write(root.join("texts/README.gmi"), "# Docs\n").unwrap();
+        fs::write(root.join("texts/bar.txt"), "# Bar\n").unwrap();
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let files = ArticleFiles {
+            extensions: vec![String::from("gmi"), String::from("txt")],
+            index: vec![String::from("README.gmi")],
+            ..ArticleFiles::default()
+        };
+        let (feeds, _) = FeedBuilder::new(&root, base)
+            .category("texts", Category::Flat)
+            .category_files("texts", files)
+            .per_category(true)
+            .build_all()
+            .unwrap();
+        let texts = &feeds[1].feed;
+        assert_eq!(texts.title(), "Docs");
+        let mut titles: Vec<&str> = texts.entries().iter().map(|e| e.title()).collect();
+        titles.sort_unstable();
+        assert_eq!(titles, vec!["Bar", "Foo", "Texts"]);
+    }
+
+    #[test]
+    fn test_build_root_category() {
+        let root = make_site("root-category");
+        fs::write(root.join("2021-03-04-post.gmi"), "# Post\n").unwrap();
+        fs::write(root.join("gemlog.gmi"), "# Generated\n").unwrap();
+        // an article merely named like the generated page
+        fs::write(root.join("texts/gemlog.gmi"), "# Gemlog\n").unwrap();
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let (feeds, _) = FeedBuilder::new(&root, base)
+            .category(ROOT_CATEGORY, Category::Flat)
+            .category("texts", Category::Flat)
+            .ignore_file("gemlog.gmi")
+            .per_category(true)
+            .build_all()
+            .unwrap();
+        let paths: Vec<&Path> = feeds.iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            vec![Path::new("atom.xml"), Path::new("texts/atom.xml")]
+        );
+        let mut links: Vec<&str> = feeds[0]
+            .feed
+            .entries()
+            .iter()
+            .map(|e| e.links()[0].href())
+            .collect();
+        links.sort_unstable();
+        assert_eq!(
+            links,
+            vec![
+                "gemini://example.org/2021-03-04-post.gmi",
+                "gemini://example.org/texts/2021-01-15-foo.gmi",
+                "gemini://example.org/texts/gemlog.gmi"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_output_subdir() {
+        let root = make_site("output-subdir");
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let (feeds, _) = FeedBuilder::new(&root, base)
+            .category("texts", Category::Flat)
+            .output("feeds/feed.json")
+            .per_category(true)
+            .build_all()
+            .unwrap();
+        let urls: Vec<&str> = feeds.iter().map(|f| f.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "gemini://example.org/feeds/feed.json",
+                "gemini://example.org/texts/feeds/feed.json"
+            ]
+        );
+        let texts = &feeds[1];
+        let path = root.join(&texts.path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        Format::JsonFeed
+            .write(&texts.feed, &path, Some(&texts.url), false)
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(json["home_page_url"], "gemini://example.org/texts/");
+        assert_eq!(
+            json["feed_url"],
+            "gemini://example.org/texts/feeds/feed.json"
+        );
+    }
+
+    #[test]
+    fn test_build_date_ties() {
+        let root = make_site("date-ties");
+        for cat in &["c", "a", "b"] {
+            fs::create_dir_all(root.join(cat)).unwrap();
+            fs::write(root.join(cat).join("2021-03-04-post.gmi"), "# Post\n").unwrap();
+        }
+        let base = Url::parse("gemini://example.org/").unwrap();
+        // each builder hashes its categories in another order
+        for _ in 0..5 {
+            let feed = FeedBuilder::new(&root, base.clone())
+                .category("c", Category::Flat)
+                .category("a", Category::Flat)
+                .category("b", Category::Flat)
+                .max_entries(2)
+                .build()
+                .unwrap();
+            let links: Vec<&str> = feed.entries().iter().map(|e| e.links()[0].href()).collect();
+            assert_eq!(
+                links,
+                vec![
+                    "gemini://example.org/a/2021-03-04-post.gmi",
+                    "gemini://example.org/b/2021-03-04-post.gmi"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_build_state_unpublished() {
+        let root = make_site("state-unpublished");
+        fs::write(root.join("texts/baz.gmi"), "draft: true\n\n# Secret\n").unwrap();
+        fs::write(root.join("texts/2999-01-01-later.gmi"), "# Later\n").unwrap();
+        let base = Url::parse("gemini://example.org/").unwrap();
+        FeedBuilder::new(&root, base)
+            .category("texts", Category::Flat)
+            .incremental(true)
+            .build()
+            .unwrap();
+        let state_path = root.join(STATE_FILE);
+        let state = fs::read_to_string(&state_path).unwrap();
+        assert!(state.contains("\"texts/2021-01-15-foo.gmi\""));
+        assert!(!state.contains("Secret"));
+        assert!(!state.contains("Later"));
+        assert!(!is_world_readable(path_str(&state_path).unwrap()));
+    }
+
+    #[test]
+    fn test_build_incremental() {
+        let root = make_site("incremental");
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let builder = FeedBuilder::new(&root, base)
+            .category("texts", Category::Flat)
+            .time_source(TimeSource::Mtime)
+            .content(Some(ContentType::Gemini))
+            .incremental(true);
+        let first = builder.build().unwrap();
+        assert!(root.join(STATE_FILE).is_file());
+
+        // same contents, later modification time: the dates are kept
+        let foo = root.join("texts/2021-01-15-foo.gmi");
+        fs::write(&foo, "# Foo\n").unwrap();
+        let second = builder.build().unwrap();
+        assert_eq!(second, first);
+
+        fs::write(&foo, "# Spam\n").unwrap();
+        let third = builder.build().unwrap();
+        assert_eq!(third.entries()[0].title(), "Spam");
+        let content = third.entries()[0].content().unwrap();
+        assert_eq!(content.value(), Some("# Spam\n"));
+    }
+
+    #[test]
+    fn test_build_ids() {
+        let root = make_site("ids");
+        fs::write(root.join("texts/bar.gmi"), "id: urn:bar\n\n# Bar\n").unwrap();
+        // tag ids are kept without --incremental
+        let builder = |base: &str| {
+            FeedBuilder::new(&root, Url::parse(base).unwrap())
+                .category("texts", Category::Flat)
+                .id_scheme(IdScheme::Tag)
+        };
+        let build = |base: &str| builder(base).build().unwrap();
+        let entry = |feed: &Feed, title: &str| {
+            let entry = feed.entries().iter().find(|e| e.title() == title);
+            entry.unwrap().clone()
+        };
+        let id = |feed: &Feed, title: &str| entry(feed, title).id().to_string();
+        let feed = build("gemini://example.org/");
+        assert_eq!(id(&feed, "Bar"), "urn:bar");
+        assert_eq!(
+            id(&feed, "Foo"),
+            "tag:example.org,2021-01-15:/texts/2021-01-15-foo.gmi"
+        );
+
+        // renamed, on a new host
+        fs::rename(
+            root.join("texts/2021-01-15-foo.gmi"),
+            root.join("texts/2021-01-15-spam.gmi"),
+        )
+        .unwrap();
+        let feed = build("gemini://example.net/");
+        assert_eq!(
+            id(&feed, "Foo"),
+            "tag:example.org,2021-01-15:/texts/2021-01-15-foo.gmi"
+        );
+        assert_eq!(
+            entry(&feed, "Foo").links()[0].href(),
+            "gemini://example.net/texts/2021-01-15-spam.gmi"
+        );
+
+        // other options, and a configured authority
+        fs::write(root.join("texts/2021-02-01-eggs.gmi"), "# Eggs\n").unwrap();
+        let feed = builder("gemini://example.net/")
+            .tag_authority("example.org,2021")
+            .clean_title(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            id(&feed, "Foo"),
+            "tag:example.org,2021-01-15:/texts/2021-01-15-foo.gmi"
+        );
+        assert_eq!(
+            id(&feed, "Eggs"),
+            "tag:example.org,2021:2021-02-01/texts/2021-02-01-eggs.gmi"
+        );
+
+        // the root spelled otherwise, and an article gone
+        fs::remove_file(root.join("texts/2021-02-01-eggs.gmi")).unwrap();
+        let feed = FeedBuilder::new(root.join("."), Url::parse("gemini://example.net/").unwrap())
+            .category("texts", Category::Flat)
+            .id_scheme(IdScheme::Tag)
+            .build()
+            .unwrap();
+        assert_eq!(
+            id(&feed, "Foo"),
+            "tag:example.org,2021-01-15:/texts/2021-01-15-foo.gmi"
+        );
+        let state = fs::read_to_string(root.join(STATE_FILE)).unwrap();
+        assert!(state.contains("\"texts/2021-01-15-spam.gmi\""));
+        assert!(!state.contains("eggs"));
+    }
+}
@@ -0,0 +1,568 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use glob::{MatchOptions, Pattern};
+use pathdiff::diff_paths;
+use serde::{Deserialize, Deserializer};
+
+use crate::error::{Diagnostics, GemAtomError, Result};
+use crate::util::{find_index, is_world_readable, path_str};
+
+/// Name of the pseudo-category made of the articles of the root
+/// directory itself.
+pub const ROOT_CATEGORY: &str = ".";
+
+/// Categories
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    /// Every gemini file of the category directory is an article,
+    /// except index files.
+    Flat,
+    /// Every article is a subdirectory of the category directory,
+    /// containing an index file.
+    Tree,
+    /// Every gemini file of the month directories of year
+    /// directories, such as `2021/03/post.gmi`, or of their day
+    /// directories, is an article, except index files.
+    Dated,
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    /// Build a category from a string.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "flat" => Ok(Category::Flat),
+            "tree" => Ok(Category::Tree),
+            "dated" => Ok(Category::Dated),
+            _ => Err(format!("Invalid category {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Category::Flat => "flat",
+            Category::Tree => "tree",
+            Category::Dated => "dated",
+        })
+    }
+}
+
+/// Which index files of a tree category are articles, by the depth of
+/// their directory below the category directory.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum TreeDepth {
+    /// Index files at any depth.
+    Any,
+    /// Index files at exactly that depth, subdirectories of the
+    /// category directory being at depth 1.
+    Exactly(usize),
+    /// The shallowest index files of each branch, the index files
+    /// below them being part of their article.
+    Shallowest,
+}
+
+impl FromStr for TreeDepth {
+    type Err = String;
+
+    /// Build a tree depth from a string, `any`, `shallowest` or a
+    /// positive number.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "any" => Ok(TreeDepth::Any),
+            "shallowest" => Ok(TreeDepth::Shallowest),
+            _ => match s.parse() {
+                Ok(n) if n > 0 => Ok(TreeDepth::Exactly(n)),
+                _ => Err(format!("Invalid tree depth {}", s)),
+            },
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TreeDepth {
+    /// Deserialize a tree depth from a number or a string.
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Depth {
+            Number(u64),
+            Name(String),
+        }
+        let s = match Depth::deserialize(deserializer)? {
+            Depth::Number(n) => n.to_string(),
+            Depth::Name(s) => s,
+        };
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Which files of a category are articles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArticleFiles {
+    /// Glob patterns of the candidate files, relative to the category
+    /// directory. When empty, every file of the directory (flat
+    /// categories) or of its subdirectories (tree and dated
+    /// categories) is a candidate.
+    pub include: Vec<Pattern>,
+    /// Extensions of the articles of flat and dated categories,
+    /// without dot.
+    pub extensions: Vec<String>,
+    /// Names of index files, which are left out of flat categories,
+    /// are the articles of tree categories, and give their title to
+    /// the feed of a category.
+    pub index: Vec<String>,
+    /// Which index files of a tree category are articles.
+    pub depth: TreeDepth,
+}
+
+impl Default for ArticleFiles {
+    /// Gemini files, with `index.gmi` or `index.gemini` index files.
+    fn default() -> Self {
+        ArticleFiles {
+            include: Vec::new(),
+            extensions: vec![String::from("gmi"), String::from("gemini")],
+            index: vec![String::from("index.gmi"), String::from("index.gemini")],
+            depth: TreeDepth::Any,
+        }
+    }
+}
+
+impl ArticleFiles {
+    /// Whether the file at path `rel`, relative to the directory
+    /// `dir` of a category of type `typ`, is an article.
+    fn is_article(&self, dir: &Path, rel: &Path, typ: Category) -> bool {
+        let is_index = match rel.file_name().and_then(|f| f.to_str()) {
+            Some(f) => self.index.iter().any(|i| i == f),
+            None => return false,
+        };
+        let has_extension = match rel.extension().and_then(|e| e.to_str()) {
+            Some(ext) => self.extensions.iter().any(|e| e == ext),
+            None => false,
+        };
+        let depth = rel.components().count() - 1;
+        match typ {
+            Category::Flat => !is_index && has_extension,
+            Category::Tree => {
+                is_index
+                    && depth > 0
+                    && match self.depth {
+                        TreeDepth::Any => true,
+                        TreeDepth::Exactly(n) => depth == n,
+                        TreeDepth::Shallowest => rel
+                            .ancestors()
+                            .skip(2)
+                            .take(depth - 1)
+                            .all(|a| find_index(&dir.join(a), &self.index).is_none()),
+                    }
+            }
+            Category::Dated => {
+                !is_index
+                    && has_extension
+                    && matches!(directory_date(rel), Some((n, _)) if n == depth)
+            }
+        }
+    }
+}
+
+/// The date, as `YYYY-MM-DD`, given by the year and month directories,
+/// and optionally the day directory, containing the file at `path`,
+/// along with the number of these directories.
+pub(crate) fn directory_date(path: &Path) -> Option<(usize, String)> {
+    let dirs: Vec<&str> = path
+        .ancestors()
+        .skip(1)
+        .take(3)
+        .filter_map(|a| a.file_name().and_then(|f| f.to_str()))
+        .collect();
+    let digits = |s: &str, n| s.len() == n && s.chars().all(|c| c.is_ascii_digit());
+    match dirs[..] {
+        [d, m, y, ..] if digits(d, 2) && digits(m, 2) && digits(y, 4) => {
+            Some((3, format!("{}-{}-{}", y, m, d)))
+        }
+        [m, y, ..] if digits(m, 2) && digits(y, 4) => Some((2, format!("{}-{}-01", y, m))),
+        _ => None,
+    }
+}
+
+/// An article found while collecting files.
+#[derive(Clone, Debug)]
+pub(crate) struct Article {
+    /// Path of the article file.
+    pub path: String,
+    /// Name of the category it belongs to.
+    pub category: String,
+    /// Type of that category.
+    pub typ: Category,
+}
+
+/// Collect all articles in a category, as told by `files`. Unusable
+/// files, such as articles whose name is not valid UTF-8, are
+/// recorded in `diags`.
+pub(crate) fn collect_articles(
+    name: &str,
+    typ: Category,
+    files: &ArticleFiles,
+    root: &str,
+    diags: &mut Diagnostics,
+) -> Result<Vec<Article>> {
+    let mut fulldir = PathBuf::new();
+    fulldir.push(root);
+    if name != ROOT_CATEGORY {
+        fulldir.push(name);
+    }
+    let defaults;
+    let patterns = if !files.include.is_empty() {
+        &files.include
+    } else {
+        let pattern = match typ {
+            Category::Flat => "*",
+            Category::Tree | Category::Dated => "**/*",
+        };
+        defaults = [Pattern::new(pattern).expect("valid glob pattern")];
+        &defaults[..]
+    };
+    let options = MatchOptions {
+        require_literal_separator: true,
+        ..MatchOptions::new()
+    };
+    let recursive = patterns.iter().any(|p| p.as_str().contains('/'));
+    let mut paths = Vec::new();
+    list_files(&fulldir, recursive, &mut paths, diags)?;
+    let mut articles: Vec<Article> = Vec::new();
+    for path in paths {
+        let rel = match diff_paths(&path, &fulldir) {
+            Some(rel) => rel,
+            None => continue,
+        };
+        // undecodable names are reported if they look like articles
+        let lossy = PathBuf::from(rel.to_string_lossy().into_owned());
+        if !patterns
+            .iter()
+            .any(|p| p.matches_path_with(&lossy, options))
+            || !files.is_article(&fulldir, &lossy, typ)
+        {
+            continue;
+        }
+        match path.to_str() {
+            Some(pathname) => articles.push(Article {
+                path: String::from(pathname),
+                category: String::from(name),
+                typ,
+            }),
+            None => diags.skip(&path, GemAtomError::InvalidPath(path.clone()))?,
+        }
+    }
+    Ok(articles
+        .into_iter()
+        .filter(|a| is_world_readable(&a.path))
+        .collect())
+}
+
+/// Add the files of directory `dir` to `paths`, sorted by name, and
+/// those of its subdirectories if `recursive`. Unreadable directories
+/// are recorded in `diags`.
+fn list_files(
+    dir: &Path,
+    recursive: bool,
+    paths: &mut Vec<PathBuf>,
+    diags: &mut Diagnostics,
+) -> Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return diags.skip(dir, GemAtomError::io(dir, e)),
+    };
+    let mut entries: Vec<PathBuf> = match entries.collect::<io::Result<Vec<_>>>() {
+        Ok(entries) => entries.into_iter().map(|e| e.path()).collect(),
+        Err(e) => return diags.skip(dir, GemAtomError::io(dir, e)),
+    };
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                list_files(&path, recursive, paths, diags)?;
+            }
+        } else if path.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Detect the categories of the site rooted at `root`, among its
+/// subdirectories, by the articles each type of category would find
+/// in them with the default article files. A subdirectory laid out by
+/// date is a dated category, one with more article subdirectories
+/// than article files a tree category, and one with several article
+/// files a flat category. Hidden subdirectories are skipped. Returns
+/// the name, type and number of articles of each category, sorted by
+/// name.
+pub fn detect_categories(root: &Path) -> Result<Vec<(String, Category, usize)>> {
+    let root_str = path_str(root)?;
+    let entries = fs::read_dir(root).map_err(|e| GemAtomError::io(root, e))?;
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| GemAtomError::io(root, e))?.path();
+        match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) if path.is_dir() && !name.starts_with('.') => names.push(name.to_string()),
+            _ => {}
+        }
+    }
+    names.sort();
+    let files = ArticleFiles::default();
+    let mut categories = Vec::new();
+    for name in names {
+        let count = |typ| -> Result<usize> {
+            let mut diags = Diagnostics::new(false);
+            Ok(collect_articles(&name, typ, &files, root_str, &mut diags)?.len())
+        };
+        let dated = count(Category::Dated)?;
+        let tree = count(Category::Tree)?;
+        let flat = count(Category::Flat)?;
+        let detected = if dated > 0 && dated >= tree.max(flat) {
+            Some((Category::Dated, dated))
+        } else if tree > 0 && tree > flat {
+            Some((Category::Tree, tree))
+        } else if flat > 1 {
+            Some((Category::Flat, flat))
+        } else {
+            None
+        };
+        if let Some((typ, n)) = detected {
+            categories.push((name, typ, n));
+        }
+    }
+    Ok(categories)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_category_from_str() {
+        assert_eq!("flat".parse(), Ok(Category::Flat));
+        assert_eq!("tree".parse(), Ok(Category::Tree));
+        assert_eq!("dated".parse(), Ok(Category::Dated));
+        assert_eq!(
+            "zorgl".parse::<Category>(),
+            Err(String::from("Invalid category zorgl"))
+        );
+    }
+
+    /// Relative paths of the articles of category `name` under
+    /// `root`, sorted.
+    fn collect(root: &Path, name: &str, typ: Category, files: &ArticleFiles) -> Vec<String> {
+        let mut diags = Diagnostics::new(false);
+        let mut paths: Vec<String> =
+            collect_articles(name, typ, files, path_str(root).unwrap(), &mut diags)
+                .unwrap()
+                .into_iter()
+                .map(|a| String::from(&a.path[root.as_os_str().len() + 1..]))
+                .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_collect_articles() {
+        let root = TempDir::with_files(
+            "collect",
+            &[
+                "docs/README.gmi",
+                "docs/foo.gmi",
+                "docs/bar.txt",
+                "docs/baz.png",
+                "docs/notes/eggs.md",
+                "posts/spam/README.gmi",
+                "posts/spam/index.gmi",
+            ],
+            "# Title\n",
+        );
+        let files = ArticleFiles {
+            include: vec![Pattern::new("*").unwrap(), Pattern::new("notes/*").unwrap()],
+            extensions: vec![String::from("gmi"), String::from("txt"), String::from("md")],
+            index: vec![String::from("README.gmi")],
+            ..ArticleFiles::default()
+        };
+        let tree_files = ArticleFiles {
+            include: Vec::new(),
+            ..files.clone()
+        };
+        assert_eq!(
+            collect(&root, "docs", Category::Flat, &files),
+            vec!["docs/bar.txt", "docs/foo.gmi", "docs/notes/eggs.md"]
+        );
+        assert_eq!(
+            collect(&root, "docs", Category::Flat, &ArticleFiles::default()),
+            vec!["docs/README.gmi", "docs/foo.gmi"]
+        );
+        assert_eq!(
+            collect(&root, "posts", Category::Tree, &tree_files),
+            vec!["posts/spam/README.gmi"]
+        );
+    }
+
+    #[test]
+    fn test_collect_invalid_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = TempDir::with_files("invalid-names", &["texts/good.gmi"], "# Good\n");
+        for name in &[&b"bad-\xff.gmi"[..], &b"image-\xff.png"[..]] {
+            let path = root.join("texts").join(OsStr::from_bytes(name));
+            fs::write(path, "# Bad\n").unwrap();
+        }
+        let bad = root.join("texts").join(OsStr::from_bytes(b"bad-\xff.gmi"));
+        let files = ArticleFiles::default();
+        let mut diags = Diagnostics::new(false);
+        let articles = collect_articles(
+            "texts",
+            Category::Flat,
+            &files,
+            path_str(&root).unwrap(),
+            &mut diags,
+        )
+        .unwrap();
+        assert_eq!(articles.len(), 1);
+        assert_eq!(diags.skipped.len(), 1);
+        assert_eq!(diags.skipped[0].path, bad);
+        assert!(matches!(
+            diags.skipped[0].error,
+            GemAtomError::InvalidPath(_)
+        ));
+        let mut strict = Diagnostics::new(true);
+        match collect_articles(
+            "texts",
+            Category::Flat,
+            &files,
+            path_str(&root).unwrap(),
+            &mut strict,
+        ) {
+            Err(GemAtomError::InvalidPath(path)) => assert_eq!(path, bad),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_detect_categories() {
+        let root = TempDir::with_files(
+            "detect",
+            &[
+                "texts/index.gmi",
+                "texts/foo.gmi",
+                "texts/bar.gmi",
+                "noise/index.gmi",
+                "noise/spam/index.gmi",
+                "noise/eggs/index.gmi",
+                "log/index.gmi",
+                "log/2021/03/post.gmi",
+                "about/index.gmi",
+                "about/me.gmi",
+                ".git/a.gmi",
+                ".git/b.gmi",
+            ],
+            "# Title\n",
+        );
+        assert_eq!(
+            detect_categories(&root).unwrap(),
+            vec![
+                (String::from("log"), Category::Dated, 1),
+                (String::from("noise"), Category::Tree, 2),
+                (String::from("texts"), Category::Flat, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collect_root_articles() {
+        let root = TempDir::with_files(
+            "root",
+            &["index.gmi", "post.gmi", "texts/foo.gmi"],
+            "# Title\n",
+        );
+        assert_eq!(
+            collect(
+                &root,
+                ROOT_CATEGORY,
+                Category::Flat,
+                &ArticleFiles::default()
+            ),
+            vec!["post.gmi"]
+        );
+    }
+
+    #[test]
+    fn test_tree_depth_from_str() {
+        assert_eq!("any".parse(), Ok(TreeDepth::Any));
+        assert_eq!("shallowest".parse(), Ok(TreeDepth::Shallowest));
+        assert_eq!("2".parse(), Ok(TreeDepth::Exactly(2)));
+        assert_eq!(
+            "0".parse::<TreeDepth>(),
+            Err(String::from("Invalid tree depth 0"))
+        );
+    }
+
+    #[test]
+    fn test_collect_nested_articles() {
+        let root = TempDir::with_files(
+            "nested",
+            &[
+                "posts/2021/spam/index.gmi",
+                "posts/2021/spam/images/index.gmi",
+                "posts/eggs/index.gmi",
+                "log/index.gmi",
+                "log/2021/index.gmi",
+                "log/2021/03/foo.gmi",
+                "log/2021/03/14/bar.gmi",
+                "log/misc/baz.gmi",
+            ],
+            "# Title\n",
+        );
+        let depth = |depth| ArticleFiles {
+            depth,
+            ..ArticleFiles::default()
+        };
+        assert_eq!(
+            collect(&root, "posts", Category::Tree, &depth(TreeDepth::Any)),
+            vec![
+                "posts/2021/spam/images/index.gmi",
+                "posts/2021/spam/index.gmi",
+                "posts/eggs/index.gmi"
+            ]
+        );
+        assert_eq!(
+            collect(
+                &root,
+                "posts",
+                Category::Tree,
+                &depth(TreeDepth::Exactly(2))
+            ),
+            vec!["posts/2021/spam/index.gmi"]
+        );
+        assert_eq!(
+            collect(
+                &root,
+                "posts",
+                Category::Tree,
+                &depth(TreeDepth::Shallowest)
+            ),
+            vec!["posts/2021/spam/index.gmi", "posts/eggs/index.gmi"]
+        );
+        assert_eq!(
+            collect(&root, "log", Category::Dated, &depth(TreeDepth::Any)),
+            vec!["log/2021/03/14/bar.gmi", "log/2021/03/foo.gmi"]
+        );
+    }
+}
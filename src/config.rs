@@ -0,0 +1,606 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::FixedOffset;
+use glob::Pattern;
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+use url::Url;
+
+use crate::builder::FeedBuilder;
+use crate::category::{ArticleFiles, Category, TreeDepth};
+use crate::content::ContentType;
+use crate::date::{parse_timezone, SortBy, TimeSource};
+use crate::error::{GemAtomError, Result};
+use crate::exclude::Exclude;
+use crate::id::IdScheme;
+use crate::output::Format;
+use crate::util::{parse_gemini_url, write_if_changed, Access};
+
+/// Name of the configuration file looked up in the site root.
+pub const CONFIG_FILE: &str = "gematom.toml";
+
+lazy_static! {
+    static ref ERROR_KEY_RE: Regex =
+        Regex::new(r"for key `([^`]*)`$|^unknown field `([^`]*)`").unwrap();
+}
+
+/// A category declared in the configuration file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryConfig {
+    /// Directory of the category, relative to the site root.
+    pub dir: String,
+    /// Category type.
+    #[serde(rename = "type")]
+    pub typ: Category,
+    /// Glob patterns of the candidate article files, relative to the
+    /// category directory.
+    #[serde(default, deserialize_with = "deserialize_patterns")]
+    pub include: Option<Vec<Pattern>>,
+    /// Extensions of the articles of a flat or dated category.
+    pub extensions: Option<Vec<String>>,
+    /// Names of the index files.
+    pub index: Option<Vec<String>>,
+    /// Depth of the articles of a tree category.
+    pub depth: Option<TreeDepth>,
+}
+
+impl CategoryConfig {
+    /// A category with the default article files.
+    pub fn new<S: Into<String>>(dir: S, typ: Category) -> Self {
+        CategoryConfig {
+            dir: dir.into(),
+            typ,
+            include: None,
+            extensions: None,
+            index: None,
+            depth: None,
+        }
+    }
+
+    /// The `[[category]]` table declaring the directory and type of
+    /// this category.
+    pub fn to_toml(&self) -> String {
+        format!(
+            "[[category]]\ndir = {}\ntype = \"{}\"\n",
+            toml::Value::String(self.dir.clone()),
+            self.typ
+        )
+    }
+
+    /// Which files of the category are articles, if they differ from
+    /// the default.
+    pub fn files(&self) -> Option<ArticleFiles> {
+        if self.include.is_none()
+            && self.extensions.is_none()
+            && self.index.is_none()
+            && self.depth.is_none()
+        {
+            return None;
+        }
+        let default = ArticleFiles::default();
+        Some(ArticleFiles {
+            include: self.include.clone().unwrap_or(default.include),
+            extensions: self.extensions.clone().unwrap_or(default.extensions),
+            index: self.index.clone().unwrap_or(default.index),
+            depth: self.depth.unwrap_or(default.depth),
+        })
+    }
+}
+
+fn deserialize_patterns<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<Pattern>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let patterns = Vec::<String>::deserialize(deserializer)?;
+    patterns
+        .iter()
+        .map(|p| Pattern::new(p).map_err(|e| format!("Invalid include pattern {}: {}", p, e)))
+        .collect::<std::result::Result<_, _>>()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Site and feed settings, as read from a `gematom.toml` file.
+///
+/// Every setting is optional, so that a configuration can be
+/// completed or overridden by another one, e.g. the command line.
+///
+/// ```toml
+/// base = "gemini://example.org/"
+/// title = "My capsule"
+/// entries = 20
+/// time_source = "mtime"
+///
+/// [[category]]
+/// dir = "texts"
+/// type = "flat"
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Root directory of the site, relative to the configuration file.
+    pub directory: Option<PathBuf>,
+    /// Base URL for feed and entries.
+    #[serde(default, deserialize_with = "deserialize_gemini_url")]
+    pub base: Option<Url>,
+    /// Categories of the site.
+    #[serde(default, rename = "category")]
+    pub categories: Vec<CategoryConfig>,
+    /// Also detect the categories of the site from its structure.
+    pub auto_categories: Option<bool>,
+    /// Output file name.
+    pub output: Option<String>,
+    /// Number of entries in the feed.
+    pub entries: Option<usize>,
+    /// Feed title.
+    pub title: Option<String>,
+    /// Feed subtitle.
+    pub subtitle: Option<String>,
+    /// Feed author name.
+    pub author: Option<String>,
+    /// Feed author email.
+    pub email: Option<String>,
+    /// Convert '_' into space in titles inferred from file names.
+    pub clean_title: Option<bool>,
+    /// Time source for undated articles.
+    pub time_source: Option<TimeSource>,
+    /// Date ordering the entries.
+    pub sort_by: Option<SortBy>,
+    /// How the ids of new entries are made.
+    pub id_scheme: Option<IdScheme>,
+    /// Authority minting the tag ids, such as `example.org,2021`.
+    pub tag_authority: Option<String>,
+    /// Patterns of the paths of articles to leave out.
+    #[serde(default, deserialize_with = "deserialize_excludes")]
+    pub exclude: Option<Vec<Exclude>>,
+    /// Timezone of the dates given without offset.
+    #[serde(default, deserialize_with = "deserialize_timezone")]
+    pub timezone: Option<FixedOffset>,
+    /// Number of paragraphs in entry summaries.
+    pub summary: Option<usize>,
+    /// Type of entry contents.
+    pub content: Option<ContentType>,
+    /// Maximum length of summaries and contents.
+    pub max_length: Option<usize>,
+    /// Abort on the first unusable article.
+    pub strict: Option<bool>,
+    /// Also generate one feed per category.
+    pub per_category: Option<bool>,
+    /// Only read again the articles changed since the previous run.
+    pub incremental: Option<bool>,
+    /// File name of the gemlog page written next to each feed.
+    pub gemlog: Option<String>,
+    /// Rewrite the links under the gemlog heading of the index file
+    /// next to each feed.
+    pub gemlog_index: Option<bool>,
+    /// Heading of the section of the index files rewritten by gematom.
+    pub gemlog_heading: Option<String>,
+    /// Keep the previous version of each feed file with a `.bak`
+    /// suffix.
+    pub backup: Option<bool>,
+    /// Formats of the written feeds.
+    pub format: Option<Vec<Format>>,
+}
+
+fn deserialize_gemini_url<'de, D>(deserializer: D) -> std::result::Result<Option<Url>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_gemini_url(&s)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_timezone<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<FixedOffset>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_timezone(&s)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_excludes<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<Vec<Exclude>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let patterns = Vec::<String>::deserialize(deserializer)?;
+    patterns
+        .iter()
+        .map(|p| p.parse())
+        .collect::<std::result::Result<_, _>>()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Write `categories` to the file at `path`, as `[[category]]` tables
+/// to paste into a configuration file.
+pub fn write_categories<P: AsRef<Path>>(path: P, categories: &[CategoryConfig]) -> Result<()> {
+    let tables: Vec<String> = categories.iter().map(CategoryConfig::to_toml).collect();
+    write_if_changed(
+        path.as_ref(),
+        tables.join("\n").as_bytes(),
+        Access::Default,
+        false,
+    )?;
+    Ok(())
+}
+
+impl Config {
+    /// Returns the path of the configuration file of the site rooted
+    /// at `root`, if there is one.
+    pub fn discover<P: AsRef<Path>>(root: P) -> Option<PathBuf> {
+        let path = root.as_ref().join(CONFIG_FILE);
+        if path.is_file() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+
+    /// Read a configuration file. A relative `directory` is resolved
+    /// against the directory of the file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| GemAtomError::io(path, e))?;
+        let mut config = Config::parse(&text).map_err(|(line, message)| GemAtomError::Config {
+            path: path.to_path_buf(),
+            line,
+            message,
+        })?;
+        if let Some(dir) = &config.directory {
+            let dir = match path.parent() {
+                Some(parent) => parent.join(dir),
+                None => dir.clone(),
+            };
+            if !dir.is_dir() {
+                return Err(GemAtomError::Config {
+                    path: path.to_path_buf(),
+                    line: key_line(&text, "directory", 0),
+                    message: format!("Invalid directory: {}", dir.display()),
+                });
+            }
+            config.directory = Some(dir);
+        }
+        Ok(config)
+    }
+
+    /// Parse the text of a configuration file. On error, returns the
+    /// line number (starting at 1) if known, and a message.
+    fn parse(text: &str) -> std::result::Result<Config, (Option<usize>, String)> {
+        toml::from_str(text).map_err(|e: toml::de::Error| {
+            let message = e.to_string();
+            // the line is reported separately
+            let message = match message.rfind(" at line ") {
+                Some(idx) => message[..idx].to_string(),
+                None => message,
+            };
+            let line = match e.line_col() {
+                // errors raised while deserializing a value are
+                // reported at the start of its table: look for the
+                // key itself.
+                Some((line, 0)) => match ERROR_KEY_RE.captures(&message) {
+                    Some(caps) => {
+                        let key = caps.get(1).or_else(|| caps.get(2)).unwrap().as_str();
+                        let key = key.rsplit('.').next().unwrap_or(key);
+                        key_line(text, key, line).or(Some(line + 1))
+                    }
+                    None => Some(line + 1),
+                },
+                Some((line, _)) => Some(line + 1),
+                None => None,
+            };
+            (line, message)
+        })
+    }
+
+    /// Settings of `other` take precedence over those of `self`.
+    /// Categories are replaced as a whole.
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            directory: other.directory.or(self.directory),
+            base: other.base.or(self.base),
+            categories: if other.categories.is_empty() {
+                self.categories
+            } else {
+                other.categories
+            },
+            auto_categories: other.auto_categories.or(self.auto_categories),
+            output: other.output.or(self.output),
+            entries: other.entries.or(self.entries),
+            title: other.title.or(self.title),
+            subtitle: other.subtitle.or(self.subtitle),
+            author: other.author.or(self.author),
+            email: other.email.or(self.email),
+            clean_title: other.clean_title.or(self.clean_title),
+            time_source: other.time_source.or(self.time_source),
+            sort_by: other.sort_by.or(self.sort_by),
+            id_scheme: other.id_scheme.or(self.id_scheme),
+            tag_authority: other.tag_authority.or(self.tag_authority),
+            exclude: other.exclude.or(self.exclude),
+            timezone: other.timezone.or(self.timezone),
+            summary: other.summary.or(self.summary),
+            content: other.content.or(self.content),
+            max_length: other.max_length.or(self.max_length),
+            strict: other.strict.or(self.strict),
+            per_category: other.per_category.or(self.per_category),
+            incremental: other.incremental.or(self.incremental),
+            gemlog: other.gemlog.or(self.gemlog),
+            gemlog_index: other.gemlog_index.or(self.gemlog_index),
+            gemlog_heading: other.gemlog_heading.or(self.gemlog_heading),
+            backup: other.backup.or(self.backup),
+            format: other.format.or(self.format),
+        }
+    }
+
+    /// Formats of the written feeds, atom by default.
+    pub fn formats(&self) -> Vec<Format> {
+        self.format.clone().unwrap_or_else(|| vec![Format::Atom])
+    }
+
+    /// Path of the file written in `format` for the feed at
+    /// `feed_path`, relative to the site root: the feed path itself
+    /// when an output file name is set, otherwise the default file
+    /// name of the format, in the same directory.
+    pub fn output_path(&self, format: Format, feed_path: &Path) -> PathBuf {
+        match self.output {
+            Some(_) => feed_path.to_path_buf(),
+            None => feed_path.with_file_name(format.file_name()),
+        }
+    }
+
+    /// Path of the gemlog page written next to the feed at
+    /// `feed_path`, relative to the site root, if any.
+    pub fn gemlog_path(&self, feed_path: &Path) -> Option<PathBuf> {
+        self.gemlog.as_ref().map(|g| feed_path.with_file_name(g))
+    }
+
+    /// Paths of the files written for the feeds at `feed_paths`, in
+    /// every format and with their backups, and of their gemlog
+    /// pages, all relative to the site root.
+    pub fn generated_files(&self, feed_paths: &[PathBuf]) -> Vec<PathBuf> {
+        let mut generated = Vec::new();
+        for feed_path in feed_paths {
+            for format in self.formats() {
+                let path = self.output_path(format, feed_path);
+                let mut bak = path.clone().into_os_string();
+                bak.push(".bak");
+                generated.push(path);
+                generated.push(PathBuf::from(bak));
+            }
+            generated.extend(self.gemlog_path(feed_path));
+        }
+        generated
+    }
+
+    /// Apply the settings present in this configuration to `builder`.
+    /// `directory` and `base` are not applied, as they are needed to
+    /// create the builder. The gemlog and format settings concern the
+    /// output of the built feeds, but the generated files are not
+    /// articles.
+    pub fn apply(&self, mut builder: FeedBuilder) -> FeedBuilder {
+        builder = builder.categories(self.categories.iter().map(|c| (c.dir.clone(), c.typ)));
+        for c in &self.categories {
+            if let Some(files) = c.files() {
+                builder = builder.category_files(c.dir.as_str(), files);
+            }
+        }
+        if let Some(o) = &self.output {
+            builder = builder.output(o.as_str());
+        }
+        if let Some(n) = self.entries {
+            builder = builder.max_entries(n);
+        }
+        if let Some(t) = &self.title {
+            builder = builder.title(t.as_str());
+        }
+        if let Some(s) = &self.subtitle {
+            builder = builder.subtitle(s.as_str());
+        }
+        if let Some(a) = &self.author {
+            builder = builder.author(a.as_str());
+        }
+        if let Some(e) = &self.email {
+            builder = builder.email(e.as_str());
+        }
+        if let Some(c) = self.clean_title {
+            builder = builder.clean_title(c);
+        }
+        if let Some(t) = self.time_source {
+            builder = builder.time_source(t);
+        }
+        if let Some(s) = self.sort_by {
+            builder = builder.sort_by(s);
+        }
+        if let Some(i) = self.id_scheme {
+            builder = builder.id_scheme(i);
+        }
+        if let Some(a) = &self.tag_authority {
+            builder = builder.tag_authority(a.as_str());
+        }
+        for pattern in self.exclude.iter().flatten() {
+            builder = builder.exclude(pattern.clone());
+        }
+        if let Some(tz) = self.timezone {
+            builder = builder.timezone(tz);
+        }
+        if let Some(s) = self.summary {
+            builder = builder.summary(s);
+        }
+        if let Some(c) = self.content {
+            builder = builder.content(Some(c));
+        }
+        if let Some(m) = self.max_length {
+            builder = builder.max_length(Some(m));
+        }
+        if let Some(s) = self.strict {
+            builder = builder.strict(s);
+        }
+        if let Some(p) = self.per_category {
+            builder = builder.per_category(p);
+        }
+        if let Some(i) = self.incremental {
+            builder = builder.incremental(i);
+        }
+        for path in self.generated_files(&builder.feed_paths()) {
+            builder = builder.ignore_file(path);
+        }
+        builder
+    }
+}
+
+/// Line number (starting at 1) of the first assignment to `key`,
+/// starting from line index `from`.
+fn key_line(text: &str, key: &str, from: usize) -> Option<usize> {
+    text.lines()
+        .skip(from)
+        .position(|l| {
+            let l = l.trim_start();
+            l.starts_with(key) && l[key.len()..].trim_start().starts_with('=')
+        })
+        .map(|idx| from + idx + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "base = \"gemini://example.org/\"\n\
+             entries = 5\n\
+             time_source = \"mtime\"\n\
+             format = [\"atom\", \"jsonfeed\"]\n\
+             timezone = \"+01:00\"\n\
+             exclude = [\"texts/old-*\", \"regex:^noise/\"]\n\
+             [[category]]\n\
+             dir = \"texts\"\n\
+             type = \"flat\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config.base,
+            Some(Url::parse("gemini://example.org/").unwrap())
+        );
+        assert_eq!(config.entries, Some(5));
+        assert_eq!(config.time_source, Some(TimeSource::Mtime));
+        assert_eq!(config.format, Some(vec![Format::Atom, Format::JsonFeed]));
+        assert_eq!(config.timezone, Some(FixedOffset::east(3600)));
+        assert_eq!(
+            config.exclude,
+            Some(vec![
+                "texts/old-*".parse().unwrap(),
+                "regex:^noise/".parse().unwrap()
+            ])
+        );
+        assert_eq!(
+            config.categories,
+            vec![CategoryConfig::new("texts", Category::Flat)]
+        );
+    }
+
+    #[test]
+    fn test_to_toml() {
+        let categories = vec![
+            CategoryConfig::new("texts", Category::Flat),
+            CategoryConfig::new("log \"2021\"", Category::Dated),
+        ];
+        let text: Vec<String> = categories.iter().map(CategoryConfig::to_toml).collect();
+        let config = Config::parse(&text.join("\n")).unwrap();
+        assert_eq!(config.categories, categories);
+    }
+
+    #[test]
+    fn test_parse_files() {
+        let config = Config::parse(
+            "[[category]]\n\
+             dir = \"docs\"\n\
+             type = \"flat\"\n\
+             include = [\"*\", \"notes/*\"]\n\
+             extensions = [\"gmi\", \"txt\", \"md\"]\n\
+             [[category]]\n\
+             dir = \"noise\"\n\
+             type = \"tree\"\n\
+             index = [\"README.gmi\"]\n\
+             depth = \"shallowest\"\n",
+        )
+        .unwrap();
+        let docs = config.categories[0].files().unwrap();
+        assert_eq!(docs.include.len(), 2);
+        assert_eq!(docs.extensions, vec!["gmi", "txt", "md"]);
+        assert_eq!(docs.index, ArticleFiles::default().index);
+        let noise = config.categories[1].files().unwrap();
+        assert!(noise.include.is_empty());
+        assert_eq!(noise.index, vec!["README.gmi"]);
+        assert_eq!(noise.depth, TreeDepth::Shallowest);
+        let (line, _) =
+            Config::parse("[[category]]\ndir = \"docs\"\ntype = \"flat\"\ninclude = [\"[\"]\n")
+                .unwrap_err();
+        assert_eq!(line, Some(4));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let (line, _) =
+            Config::parse("title = \"t\"\nbase = \"http://example.org/\"\n").unwrap_err();
+        assert_eq!(line, Some(2));
+        let (line, message) =
+            Config::parse("[[category]]\ndir = \"texts\"\ntype = \"zorgl\"\n").unwrap_err();
+        assert_eq!(line, Some(3));
+        assert!(message.contains("zorgl"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let file = Config {
+            title: Some(String::from("file")),
+            entries: Some(5),
+            ..Config::default()
+        };
+        let cli = Config {
+            title: Some(String::from("cli")),
+            ..Config::default()
+        };
+        let config = file.merge(cli);
+        assert_eq!(config.title, Some(String::from("cli")));
+        assert_eq!(config.entries, Some(5));
+    }
+
+    #[test]
+    fn test_generated_files() {
+        let config = Config {
+            gemlog: Some(String::from("gemlog.gmi")),
+            format: Some(vec![Format::Atom, Format::Rss]),
+            ..Config::default()
+        };
+        let feed_paths = [PathBuf::from("atom.xml"), PathBuf::from("texts/atom.xml")];
+        let generated = config.generated_files(&feed_paths);
+        let expected: Vec<PathBuf> = [
+            "atom.xml",
+            "atom.xml.bak",
+            "rss.xml",
+            "rss.xml.bak",
+            "gemlog.gmi",
+            "texts/atom.xml",
+            "texts/atom.xml.bak",
+            "texts/rss.xml",
+            "texts/rss.xml.bak",
+            "texts/gemlog.gmi",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(generated, expected);
+    }
+}
@@ -0,0 +1,163 @@
+use std::str::FromStr;
+
+use atom_syndication::Content;
+use serde::Deserialize;
+use url::Url;
+
+use crate::gemtext::{Document, Line};
+use crate::html;
+
+/// How the content of an article is included in its feed entry.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    /// The gemtext source, as `text/gemini`.
+    Gemini,
+    /// The gemtext converted into HTML, escaped.
+    Html,
+    /// The gemtext converted into inline XHTML.
+    Xhtml,
+}
+
+impl FromStr for ContentType {
+    type Err = String;
+
+    /// Build a content type from a string.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "gemini" => Ok(ContentType::Gemini),
+            "html" => Ok(ContentType::Html),
+            "xhtml" => Ok(ContentType::Xhtml),
+            _ => Err(format!("Invalid content type {}", s)),
+        }
+    }
+}
+
+/// What to include in feed entries besides their title.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ContentOptions {
+    /// Number of paragraphs of the summary, 0 for no summary.
+    pub summary: usize,
+    /// Type of the content, `None` for no content.
+    pub content: Option<ContentType>,
+    /// Maximum length of summary and content, in characters.
+    pub max_length: Option<usize>,
+}
+
+impl ContentOptions {
+    /// The summary of the gemtext `doc`, if summaries are wanted.
+    pub fn summary(&self, doc: &Document) -> Option<String> {
+        if self.summary == 0 {
+            return None;
+        }
+        extract_summary(doc, self.summary).map(|s| truncate(&s, self.max_length))
+    }
+
+    /// The content of an entry made of the gemtext `doc`, if contents
+    /// are wanted. Relative links are resolved against `url`, the URL
+    /// of the entry.
+    pub fn content(&self, doc: &Document, url: &Url) -> Option<Content> {
+        let typ = self.content?;
+        let text = doc.text();
+        let source = truncate(text, self.max_length);
+        // only parse again if the text was truncated
+        let truncated;
+        let doc = if source.len() == text.len() {
+            doc
+        } else {
+            truncated = Document::parse(&source);
+            &truncated
+        };
+        let mut content = Content::default();
+        match typ {
+            ContentType::Gemini => {
+                content.set_value(source.clone());
+                content.set_content_type(String::from("text/gemini"));
+            }
+            ContentType::Html => {
+                content.set_value(html::render(doc.lines(), url));
+                content.set_content_type(String::from("html"));
+            }
+            ContentType::Xhtml => {
+                content.set_value(html::render_xhtml_div(doc.lines(), url));
+                content.set_content_type(String::from("xhtml"));
+            }
+        }
+        Some(content)
+    }
+}
+
+/// Truncate `text` to at most `max` characters, marking the cut with
+/// an ellipsis.
+pub(crate) fn truncate(text: &str, max: Option<usize>) -> String {
+    match max {
+        Some(max) if text.chars().count() > max => {
+            let cut: String = text.chars().take(max).collect();
+            format!("{}…", cut.trim_end())
+        }
+        _ => String::from(text),
+    }
+}
+
+/// Extract the first `paragraphs` paragraphs following the first
+/// heading of a gemtext. A paragraph is a run of text, list or quote
+/// lines; links, headings and preformatted text are skipped.
+pub(crate) fn extract_summary(doc: &Document, paragraphs: usize) -> Option<String> {
+    let mut found: Vec<String> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    for line in doc.after_first_heading() {
+        if found.len() == paragraphs {
+            break;
+        }
+        let text = match line {
+            Line::Text(t) | Line::ListItem(t) | Line::Quote(t) => t.trim(),
+            _ => "",
+        };
+        if text.is_empty() {
+            if !current.is_empty() {
+                found.push(current.join(" "));
+                current.clear();
+            }
+        } else {
+            current.push(text);
+        }
+    }
+    if !current.is_empty() && found.len() < paragraphs {
+        found.push(current.join(" "));
+    }
+    if found.is_empty() {
+        None
+    } else {
+        Some(found.join("\n\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "intro\n# Title\n\nFirst line\nof the first paragraph.\n\
+                        => gemini://example.org/ a link\n* item\n\n```\ncode\n```\n> quoted\n";
+
+    #[test]
+    fn test_extract_summary() {
+        assert_eq!(
+            extract_summary(&Document::parse(TEXT), 1),
+            Some(String::from("First line of the first paragraph."))
+        );
+        assert_eq!(
+            extract_summary(&Document::parse(TEXT), 3),
+            Some(String::from(
+                "First line of the first paragraph.\n\nitem\n\nquoted"
+            ))
+        );
+        assert_eq!(extract_summary(&Document::parse("# Title only\n"), 1), None);
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("spam and eggs", Some(8)), "spam and…");
+        assert_eq!(truncate("spam", Some(8)), "spam");
+        assert_eq!(truncate("spam", None), "spam");
+    }
+}
@@ -0,0 +1,450 @@
+use std::cell::OnceCell;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time;
+
+use atom_syndication::FixedDateTime;
+use chrono::prelude::*;
+use chrono::NaiveDateTime;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::category::{directory_date, Category};
+use crate::error::{GemAtomError, Result};
+use crate::git::History;
+use crate::meta::Metadata;
+use crate::util::path_str;
+
+lazy_static! {
+    /// A rfc3339 date, optionally followed by a time and an offset,
+    /// or its compact form, as in `2021-03-04T14:30+01:00` or
+    /// `20210304T1430`.
+    static ref RFC3339_RE: Regex = Regex::new(
+        r"^(?:\d{4}-\d{2}-\d{2}(?:T\d{2}:\d{2}(?::\d{2})?(?:Z|[+-]\d{2}:\d{2})?)?|\d{4}[01]\d[0-3]\d(?:T\d{4}(?:\d{2})?(?:Z|[+-]\d{4})?)?)"
+    )
+    .unwrap();
+}
+
+/// Parse a timezone given as an offset from UTC, such as `+01:00`,
+/// `-0500` or `Z`.
+pub fn parse_timezone(val: &str) -> std::result::Result<FixedOffset, String> {
+    let invalid = || format!("Invalid timezone {}", val);
+    if val == "Z" || val == "UTC" {
+        return Ok(FixedOffset::east(0));
+    }
+    let sign = match val.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    let digits: String = val[1..].chars().filter(|c| *c != ':').collect();
+    if !(digits.len() == 2 || digits.len() == 4) || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let hours: i32 = digits[0..2].parse().map_err(|_| invalid())?;
+    let minutes: i32 = digits.get(2..).unwrap_or("0").parse().unwrap_or(0);
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+/// Parse a date in one of the forms matched by `RFC3339_RE`, or any
+/// rfc3339 date. Dates without offset are in the `tz` timezone, and
+/// dates without time at midnight.
+pub(crate) fn parse_date(value: &str, tz: FixedOffset) -> Result<FixedDateTime> {
+    let error = |e| GemAtomError::DateParse {
+        value: value.to_string(),
+        source: e,
+    };
+    match RFC3339_RE.find(value) {
+        Some(m) if m.end() == value.len() => {}
+        _ => return FixedDateTime::parse_from_rfc3339(value).map_err(error),
+    }
+    // split the offset off, as its sign looks like a date separator
+    let (datetime, offset) = match value.find('T') {
+        Some(t) => match value[t..].find(['Z', '+', '-']) {
+            Some(o) => (&value[..t + o], Some(&value[t + o..])),
+            None => (value, None),
+        },
+        None => (value, None),
+    };
+    let mut normalized: String = datetime
+        .chars()
+        .filter(|c| *c != '-' && *c != ':')
+        .collect();
+    match normalized.len() {
+        8 => normalized.push_str("T000000"),
+        13 => normalized.push_str("00"),
+        _ => {}
+    }
+    let offset = match offset {
+        Some("Z") => String::from("+0000"),
+        Some(o) => o.replace(':', ""),
+        None => tz.to_string().replace(':', ""),
+    };
+    normalized.push_str(&offset);
+    DateTime::parse_from_str(&normalized, "%Y%m%dT%H%M%S%z").map_err(error)
+}
+
+/// Where the update date of an article comes from when its metadata
+/// do not give one.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    /// File creation time, or change time where the filesystem does
+    /// not report it.
+    Ctime,
+    /// File last modification time.
+    Mtime,
+    /// Dates of the first and last commits of the file, from the
+    /// history of the git repository holding the site.
+    Git,
+}
+
+impl TimeSource {
+    /// Returns the file time of a file according to this time source,
+    /// along with the name of the file time actually used. The git
+    /// time source falls back here to the last modification time, for
+    /// files which were never committed.
+    fn time_with_origin(self, fname: &str) -> Result<(time::SystemTime, &'static str)> {
+        let meta = fs::metadata(fname).map_err(|e| GemAtomError::io(fname, e))?;
+        match self {
+            TimeSource::Ctime => Ok(ctime(&meta)),
+            TimeSource::Mtime | TimeSource::Git => meta
+                .modified()
+                .map(|t| (t, MTIME))
+                .map_err(|e| GemAtomError::io(fname, e)),
+        }
+    }
+}
+
+/// Which date of the articles orders the feeds, most recent first.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// Publication date, or update date for articles without one.
+    Published,
+    /// Update date.
+    Updated,
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    /// Build a sort order from a string.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "published" => Ok(SortBy::Published),
+            "updated" => Ok(SortBy::Updated),
+            _ => Err(format!("Invalid sort order {}", s)),
+        }
+    }
+}
+
+impl FromStr for TimeSource {
+    type Err = String;
+
+    /// Build a time source from a string.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "ctime" => Ok(TimeSource::Ctime),
+            "mtime" => Ok(TimeSource::Mtime),
+            "git" => Ok(TimeSource::Git),
+            _ => Err(format!("Invalid time source {}", s)),
+        }
+    }
+}
+
+const MTIME: &str = "modification time";
+
+/// Returns the creation time of a file, if the filesystem reports
+/// it, otherwise its last inode change time, or as a last resort its
+/// last modification time. The name of the time used comes along.
+fn ctime(meta: &fs::Metadata) -> (time::SystemTime, &'static str) {
+    if let Ok(t) = meta.created() {
+        return (t, "birth time");
+    }
+    if meta.ctime() > 0 {
+        let t =
+            time::UNIX_EPOCH + time::Duration::new(meta.ctime() as u64, meta.ctime_nsec() as u32);
+        return (t, "inode change time");
+    }
+    (meta.modified().unwrap_or(time::UNIX_EPOCH), MTIME)
+}
+
+/// Number of seconds elapsed since the epoch. Times before the epoch
+/// count as the epoch.
+pub(crate) fn epoch_secs(t: time::SystemTime) -> u64 {
+    t.duration_since(time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Dates articles, from their metadata, their name or the time
+/// source.
+#[derive(Clone, Debug)]
+pub(crate) struct Dater {
+    time_source: TimeSource,
+    tz: FixedOffset,
+    root: PathBuf,
+    /// Git history of the site, read on first use.
+    history: OnceCell<History>,
+}
+
+impl Dater {
+    /// Build a dater using `time_source`, for the site rooted at
+    /// `root`. Dates without offset are in the `tz` timezone.
+    pub fn new(time_source: TimeSource, tz: FixedOffset, root: &Path) -> Dater {
+        Dater {
+            time_source,
+            tz,
+            root: root.to_path_buf(),
+            history: OnceCell::new(),
+        }
+    }
+
+    /// Get the publication time of a file.
+    ///
+    /// A publication date given in the metadata `meta` of the file
+    /// wins. Otherwise, if the file is in a flat category and its
+    /// name starts with a rfc3339 date, use it. If the file is in a
+    /// tree category, then it is an "index" file, and the date may
+    /// start the name of its parent dir. If the file is in a dated
+    /// category, its name may start with a date, otherwise its
+    /// directories give the date. Names which merely look like a
+    /// date, such as `20211399-notes.gmi`, are not dates. Otherwise,
+    /// with the git time source, use the date of the first commit of
+    /// the file, and for other files their time from the time source,
+    /// by default their creation time, which edits do not move.
+    pub fn publish_time(
+        &self,
+        filepath: &str,
+        meta: &Metadata,
+        cat: Category,
+    ) -> Result<Option<FixedDateTime>> {
+        if let Some(date) = &meta.published {
+            return parse_date(date, self.tz).map(Some);
+        }
+        let path = Path::new(filepath);
+        let named = match cat {
+            Category::Flat | Category::Dated => path.file_name(),
+            Category::Tree => path.parent().and_then(|p| p.file_name()),
+        };
+        let basename = match named {
+            Some(n) => path_str(Path::new(n))?,
+            None => "",
+        };
+        if let Some(date) = name_date(basename, self.tz) {
+            return Ok(Some(date));
+        }
+        if cat == Category::Dated {
+            let date = directory_date(path).and_then(|(_, date)| parse_date(&date, self.tz).ok());
+            if date.is_some() {
+                return Ok(date);
+            }
+        }
+        if let Some((first, _)) = self.commit_times(filepath)? {
+            return Ok(Some(first));
+        }
+        let (t, _) = self.time_source.time_with_origin(filepath)?;
+        Ok(Some(utc(epoch_secs(t) as i64)))
+    }
+
+    /// Get the update time of a file, along with where it comes
+    /// from.
+    ///
+    /// An update date given in the metadata `meta` of the file wins.
+    /// Otherwise, with the git time source, use the date of the last
+    /// commit of the file, and for other files, or files never
+    /// committed, their last modification time, so that edits show.
+    /// The update time is never before the `published` time.
+    pub fn update_time(
+        &self,
+        filepath: &str,
+        meta: &Metadata,
+        published: Option<FixedDateTime>,
+    ) -> Result<(FixedDateTime, &'static str)> {
+        if let Some(date) = &meta.updated {
+            return parse_date(date, self.tz).map(|d| (d, "metadata"));
+        }
+        let (updated, origin) = match self.commit_times(filepath)? {
+            Some((_, last)) => (last, "git history"),
+            None => {
+                let (t, origin) = TimeSource::Mtime.time_with_origin(filepath)?;
+                (utc(epoch_secs(t) as i64), origin)
+            }
+        };
+        Ok(match published {
+            Some(p) if p > updated => (p, "publication date"),
+            _ => (updated, origin),
+        })
+    }
+
+    /// First and last commit times of a file, with the git time
+    /// source. The history is only read when a file needs it, which
+    /// incremental runs seldom do.
+    fn commit_times(&self, filepath: &str) -> Result<Option<(FixedDateTime, FixedDateTime)>> {
+        if self.time_source != TimeSource::Git {
+            return Ok(None);
+        }
+        if self.history.get().is_none() {
+            let _ = self.history.set(History::read(&self.root)?);
+        }
+        Ok(self.history.get().and_then(|history| {
+            history
+                .times(filepath)
+                .map(|(first, last)| (utc(first), utc(last)))
+        }))
+    }
+}
+
+/// The UTC date `secs` seconds after the epoch.
+fn utc(secs: i64) -> FixedDateTime {
+    FixedDateTime::from_utc(NaiveDateTime::from_timestamp(secs, 0), FixedOffset::east(0))
+}
+
+/// The date in front of a file name, in any of the forms matched by
+/// `RFC3339_RE`, if it is a valid date.
+fn name_date(filename: &str, tz: FixedOffset) -> Option<FixedDateTime> {
+    let m = RFC3339_RE.find(filename)?;
+    parse_date(m.as_str(), tz).ok()
+}
+
+/// Remove the rfc3339 date in front of a file name if present, in
+/// any of the forms matched by `RFC3339_RE`. If the next chars after
+/// the date are '-', '_' or a space, skip them.
+pub(crate) fn remove_rfc3339_date(filename: &str) -> &str {
+    match RFC3339_RE.find(filename) {
+        Some(m) if name_date(filename, FixedOffset::east(0)).is_some() => {
+            filename[m.end()..].trim_start_matches(|c: char| "_-".contains(c) || c.is_whitespace())
+        }
+        _ => filename,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn test_remove_rfc3339_date() {
+        assert_eq!(remove_rfc3339_date("2021-01-15-another"), "another");
+        assert_eq!(remove_rfc3339_date("2021-01-15_ spam"), "spam");
+        assert_eq!(remove_rfc3339_date("2021-01-15"), "");
+        assert_eq!(remove_rfc3339_date("foo"), "foo");
+        assert_eq!(remove_rfc3339_date("2021-01-15T14:30+01:00-spam"), "spam");
+        assert_eq!(remove_rfc3339_date("20210115T1430_spam"), "spam");
+        assert_eq!(remove_rfc3339_date("20210115 spam"), "spam");
+        assert_eq!(remove_rfc3339_date("12345678-spam"), "12345678-spam");
+        assert_eq!(remove_rfc3339_date("20211399-notes"), "20211399-notes");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let tz = FixedOffset::east(3600);
+        let date = |s| parse_date(s, tz).unwrap().to_rfc3339();
+        assert_eq!(date("2021-03-04"), "2021-03-04T00:00:00+01:00");
+        assert_eq!(date("20210304"), "2021-03-04T00:00:00+01:00");
+        assert_eq!(date("2021-03-04T14:30"), "2021-03-04T14:30:00+01:00");
+        assert_eq!(date("2021-03-04T14:30:15Z"), "2021-03-04T14:30:15+00:00");
+        assert_eq!(date("2021-03-04T14:30-05:00"), "2021-03-04T14:30:00-05:00");
+        assert_eq!(date("20210304T143015-0500"), "2021-03-04T14:30:15-05:00");
+        assert_eq!(
+            date("2021-03-04T14:30:15.5+02:00"),
+            "2021-03-04T14:30:15.500+02:00"
+        );
+        assert!(parse_date("2021-03-04 nope", tz).is_err());
+    }
+
+    #[test]
+    fn test_time_with_origin() {
+        let (_, origin) = TimeSource::Mtime.time_with_origin("/etc/hosts").unwrap();
+        assert_eq!(origin, MTIME);
+        let (_, origin) = TimeSource::Ctime.time_with_origin("/etc/hosts").unwrap();
+        assert!(["birth time", "inode change time"].contains(&origin));
+        assert!(TimeSource::Ctime.time_with_origin("/nowhere").is_err());
+    }
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!(parse_timezone("Z"), Ok(FixedOffset::east(0)));
+        assert_eq!(parse_timezone("+01:00"), Ok(FixedOffset::east(3600)));
+        assert_eq!(parse_timezone("-0530"), Ok(FixedOffset::west(19800)));
+        assert_eq!(parse_timezone("+02"), Ok(FixedOffset::east(7200)));
+        assert!(parse_timezone("Europe/Paris").is_err());
+    }
+
+    #[test]
+    fn test_publish_time_bad_date() {
+        let names = ["2021-13-45-bad.gmi", "10000000-x.gmi", "20211399-notes.gmi"];
+        let root = TempDir::with_files("bad-date", &names, "# Title\n");
+        let dater = Dater::new(TimeSource::Mtime, FixedOffset::east(0), &root);
+        // names looking like a date are undated, and dated by the
+        // time source instead
+        for name in &names {
+            let path = root.join(name);
+            let mtime = fs::metadata(&path).unwrap().modified().unwrap();
+            let published = dater.publish_time(
+                path_str(&path).unwrap(),
+                &Metadata::default(),
+                Category::Flat,
+            );
+            assert_eq!(
+                published.unwrap(),
+                Some(utc(epoch_secs(mtime) as i64)),
+                "{}",
+                name
+            );
+        }
+        // but a bad date in the metadata is an error
+        let meta = Metadata {
+            published: Some(String::from("2021-13-45")),
+            ..Metadata::default()
+        };
+        match dater.publish_time("/nowhere/bad.gmi", &meta, Category::Flat) {
+            Err(GemAtomError::DateParse { value, .. }) => assert_eq!(value, "2021-13-45"),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_git_history_lazy() {
+        // no repository there, which only matters for undated files
+        let root = TempDir::new("lazy");
+        let dater = Dater::new(TimeSource::Git, FixedOffset::east(0), &root);
+        let meta = Metadata::default();
+        let dated = root.join("2021-03-04-post.gmi");
+        let published = dater.publish_time(path_str(&dated).unwrap(), &meta, Category::Flat);
+        assert!(published.unwrap().is_some());
+        assert!(dater.history.get().is_none());
+        let undated = root.join("post.gmi");
+        match dater.publish_time(path_str(&undated).unwrap(), &meta, Category::Flat) {
+            Err(GemAtomError::Git { .. }) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_publish_time_dated() {
+        let dater = Dater::new(TimeSource::Mtime, FixedOffset::east(0), Path::new("/"));
+        let published = |path| {
+            dater
+                .publish_time(path, &Metadata::default(), Category::Dated)
+                .unwrap()
+                .map(|d| d.to_rfc3339())
+        };
+        assert_eq!(
+            published("/log/2021/03/post.gmi"),
+            Some(String::from("2021-03-01T00:00:00+00:00"))
+        );
+        assert_eq!(
+            published("/log/2021/03/14/post.gmi"),
+            Some(String::from("2021-03-14T00:00:00+00:00"))
+        );
+        assert_eq!(
+            published("/log/2021/03/2021-03-20-post.gmi"),
+            Some(String::from("2021-03-20T00:00:00+00:00"))
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Differences between two versions of a feed.
+
+use std::fmt;
+
+use atom_syndication::{Entry, Feed};
+
+use crate::rss_feed::link_href;
+
+/// A change of a feed entry, entries being matched by id.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// An entry of the new feed only.
+    Added(Entry),
+    /// An entry of the old feed only.
+    Removed(Entry),
+    /// An entry of both feeds whose title, update date or link
+    /// changed, along with its old version.
+    Modified { old: Box<Entry>, entry: Entry },
+}
+
+/// Link of an entry, if any.
+fn link(entry: &Entry) -> &str {
+    link_href(entry.links(), "alternate").unwrap_or("no link")
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |f: &mut fmt::Formatter, what: &str, e: &Entry| {
+            write!(
+                f,
+                "{} \"{}\", updated {}, {}",
+                what,
+                e.title(),
+                e.updated().to_rfc3339(),
+                link(e)
+            )
+        };
+        match self {
+            Change::Added(e) => describe(f, "added", e),
+            Change::Removed(e) => describe(f, "removed", e),
+            Change::Modified { old, entry } => {
+                write!(f, "modified \"{}\":", entry.title())?;
+                let mut sep = " ";
+                if old.title() != entry.title() {
+                    write!(
+                        f,
+                        "{}title \"{}\" -> \"{}\"",
+                        sep,
+                        old.title(),
+                        entry.title()
+                    )?;
+                    sep = ", ";
+                }
+                if old.updated() != entry.updated() {
+                    write!(
+                        f,
+                        "{}updated {} -> {}",
+                        sep,
+                        old.updated().to_rfc3339(),
+                        entry.updated().to_rfc3339()
+                    )?;
+                    sep = ", ";
+                }
+                if link(old) != link(entry) {
+                    write!(f, "{}link {} -> {}", sep, link(old), link(entry))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// List the changes of entries from `old` to `new`: added and
+/// modified entries in the order of `new`, then removed entries in
+/// the order of `old`.
+pub fn changes(old: &Feed, new: &Feed) -> Vec<Change> {
+    let find = |feed: &Feed, id: &str| feed.entries().iter().find(|e| e.id() == id).cloned();
+    let mut changes = Vec::new();
+    for entry in new.entries() {
+        match find(old, entry.id()) {
+            None => changes.push(Change::Added(entry.clone())),
+            Some(o)
+                if o.title() != entry.title()
+                    || o.updated() != entry.updated()
+                    || link(&o) != link(entry) =>
+            {
+                changes.push(Change::Modified {
+                    old: Box::new(o),
+                    entry: entry.clone(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    for entry in old.entries() {
+        if find(new, entry.id()).is_none() {
+            changes.push(Change::Removed(entry.clone()));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atom_syndication::{FixedDateTime, Link};
+
+    fn entry(id: &str, title: &str) -> Entry {
+        let mut e = Entry::default();
+        e.set_id(id);
+        e.set_title(title);
+        e.set_updated(FixedDateTime::parse_from_rfc3339("2021-03-04T00:00:00Z").unwrap());
+        let mut link = Link::default();
+        link.set_href(format!("gemini://example.org/{}.gmi", id));
+        e.set_links(vec![link]);
+        e
+    }
+
+    #[test]
+    fn test_changes() {
+        let mut old = Feed::default();
+        old.set_entries(vec![entry("a", "A"), entry("b", "B"), entry("c", "C")]);
+        let mut new = Feed::default();
+        new.set_entries(vec![entry("d", "D"), entry("a", "A"), entry("b", "Bee")]);
+        let changes = changes(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                Change::Added(entry("d", "D")),
+                Change::Modified {
+                    old: Box::new(entry("b", "B")),
+                    entry: entry("b", "Bee"),
+                },
+                Change::Removed(entry("c", "C")),
+            ]
+        );
+        assert_eq!(
+            changes[1].to_string(),
+            "modified \"Bee\": title \"B\" -> \"Bee\""
+        );
+        assert_eq!(
+            changes[0].to_string(),
+            "added \"D\", updated 2021-03-04T00:00:00+00:00, gemini://example.org/d.gmi"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Errors raised while building or writing a feed.
+#[derive(Debug)]
+pub enum GemAtomError {
+    /// An I/O operation failed on `path`.
+    Io { path: PathBuf, source: io::Error },
+    /// A pathname is not valid UTF-8.
+    InvalidPath(PathBuf),
+    /// `path` could not be joined to the base URL `base`.
+    UrlJoin {
+        base: String,
+        path: String,
+        source: url::ParseError,
+    },
+    /// A date could not be parsed.
+    DateParse {
+        value: String,
+        source: chrono::ParseError,
+    },
+    /// The feed could not be written to `path`.
+    FeedWrite {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The git repository holding `path` could not be read.
+    Git { path: PathBuf, source: git2::Error },
+    /// The configuration file `path` is invalid.
+    Config {
+        path: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+}
+
+/// Result type used throughout gematom.
+pub type Result<T> = std::result::Result<T, GemAtomError>;
+
+/// An article skipped because of an error.
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// The offending article (or directory).
+    pub path: PathBuf,
+    /// Why it was skipped.
+    pub error: GemAtomError,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error {
+            // these already mention the path
+            GemAtomError::Io { .. } | GemAtomError::InvalidPath(_) => write!(f, "{}", self.error),
+            e => write!(f, "{}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// Collects per-file diagnostics while building a feed. In strict
+/// mode, the first error aborts the build instead.
+pub(crate) struct Diagnostics {
+    strict: bool,
+    pub skipped: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new(strict: bool) -> Self {
+        Diagnostics {
+            strict,
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Record that `path` is skipped because of `error`, or return
+    /// `error` in strict mode.
+    pub fn skip<P: Into<PathBuf>>(&mut self, path: P, error: GemAtomError) -> Result<()> {
+        if self.strict {
+            return Err(error);
+        }
+        self.skipped.push(Diagnostic {
+            path: path.into(),
+            error,
+        });
+        Ok(())
+    }
+}
+
+impl GemAtomError {
+    /// Build an I/O error on `path`.
+    pub(crate) fn io<P: Into<PathBuf>>(path: P, source: io::Error) -> Self {
+        GemAtomError::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Process exit code for this class of error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GemAtomError::Io { .. } => 2,
+            GemAtomError::InvalidPath(_) => 3,
+            GemAtomError::UrlJoin { .. } => 4,
+            GemAtomError::DateParse { .. } => 5,
+            GemAtomError::FeedWrite { .. } => 6,
+            GemAtomError::Config { .. } => 7,
+            GemAtomError::Git { .. } => 8,
+        }
+    }
+}
+
+impl fmt::Display for GemAtomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GemAtomError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            GemAtomError::InvalidPath(path) => {
+                write!(f, "{}: pathname is not valid UTF-8", path.display())
+            }
+            GemAtomError::UrlJoin { base, path, source } => {
+                write!(f, "cannot join {} to {}: {}", path, base, source)
+            }
+            GemAtomError::DateParse { value, source } => {
+                write!(f, "invalid date {}: {}", value, source)
+            }
+            GemAtomError::FeedWrite { path, source } => {
+                write!(f, "cannot write feed {}: {}", path.display(), source)
+            }
+            GemAtomError::Git { path, source } => {
+                write!(
+                    f,
+                    "cannot read git history of {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            GemAtomError::Config {
+                path,
+                line: Some(line),
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            GemAtomError::Config {
+                path,
+                line: None,
+                message,
+            } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for GemAtomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GemAtomError::Io { source, .. } => Some(source),
+            GemAtomError::InvalidPath(_) => None,
+            GemAtomError::UrlJoin { source, .. } => Some(source),
+            GemAtomError::DateParse { source, .. } => Some(source),
+            GemAtomError::FeedWrite { source, .. } => Some(source.as_ref()),
+            GemAtomError::Git { source, .. } => Some(source),
+            GemAtomError::Config { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics_skip() {
+        let mut diags = Diagnostics::new(false);
+        assert!(diags
+            .skip("a.gmi", GemAtomError::InvalidPath(PathBuf::from("a.gmi")))
+            .is_ok());
+        assert_eq!(diags.skipped.len(), 1);
+        assert_eq!(
+            diags.skipped[0].to_string(),
+            "a.gmi: pathname is not valid UTF-8"
+        );
+
+        let mut diags = Diagnostics::new(true);
+        assert!(diags
+            .skip("a.gmi", GemAtomError::InvalidPath(PathBuf::from("a.gmi")))
+            .is_err());
+        assert!(diags.skipped.is_empty());
+    }
+}
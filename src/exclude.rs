@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use glob::Pattern;
+use ignore::gitignore::Gitignore;
+use ignore::Match;
+use regex::Regex;
+
+use crate::error::{GemAtomError, Result};
+
+/// Name of the files listing, in gitignore syntax, the articles of
+/// their directory and subdirectories to leave out of the feeds.
+pub const IGNORE_FILE: &str = ".gematomignore";
+
+/// Prefix of exclusion patterns given as regular expressions.
+const REGEX_PREFIX: &str = "regex:";
+
+/// A pattern excluding articles, matched against their path relative
+/// to the site root.
+#[derive(Clone, Debug)]
+pub enum Exclude {
+    /// A glob pattern, whose `*` also matches `/`.
+    Glob(Pattern),
+    /// A regular expression, written with a `regex:` prefix.
+    Regex(Box<Regex>),
+}
+
+impl FromStr for Exclude {
+    type Err = String;
+
+    /// Build an exclusion pattern from a string.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        let invalid = |e: &dyn std::fmt::Display| format!("Invalid exclude pattern {}: {}", s, e);
+        if let Some(re) = s.strip_prefix(REGEX_PREFIX) {
+            Regex::new(re)
+                .map(|r| Exclude::Regex(Box::new(r)))
+                .map_err(|e| invalid(&e))
+        } else {
+            Pattern::new(s).map(Exclude::Glob).map_err(|e| invalid(&e))
+        }
+    }
+}
+
+/// Patterns are equal when written the same way.
+impl PartialEq for Exclude {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Exclude::Glob(a), Exclude::Glob(b)) => a == b,
+            (Exclude::Regex(a), Exclude::Regex(b)) => a.as_str() == b.as_str(),
+            _ => false,
+        }
+    }
+}
+
+impl Exclude {
+    fn matches(&self, relpath: &str) -> bool {
+        match self {
+            Exclude::Glob(p) => p.matches(relpath),
+            Exclude::Regex(r) => r.is_match(relpath),
+        }
+    }
+}
+
+/// Decides which articles are excluded, by pattern or by the
+/// `.gematomignore` files of the directories between the site root
+/// and the article.
+pub(crate) struct Excluder<'a> {
+    root: &'a Path,
+    patterns: &'a [Exclude],
+    /// Ignore files already read, by directory.
+    ignores: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl<'a> Excluder<'a> {
+    pub fn new(root: &'a Path, patterns: &'a [Exclude]) -> Self {
+        Excluder {
+            root,
+            patterns,
+            ignores: HashMap::new(),
+        }
+    }
+
+    /// Whether the article at `path` is excluded.
+    pub fn is_excluded(&mut self, path: &Path) -> Result<bool> {
+        let relpath = match path.strip_prefix(self.root) {
+            Ok(r) => r,
+            Err(_) => return Ok(false),
+        };
+        let relstr = relpath.to_string_lossy();
+        if self.patterns.iter().any(|p| p.matches(&relstr)) {
+            return Ok(true);
+        }
+        // the deepest ignore file having a say wins, as with git
+        let mut dir = path.parent();
+        while let Some(d) = dir {
+            if let Some(ignore) = self.ignore(d)? {
+                match ignore.matched_path_or_any_parents(path, false) {
+                    Match::Ignore(_) => return Ok(true),
+                    Match::Whitelist(_) => return Ok(false),
+                    Match::None => {}
+                }
+            }
+            if d == self.root {
+                break;
+            }
+            dir = d.parent();
+        }
+        Ok(false)
+    }
+
+    /// The ignore file of directory `dir`, if any.
+    fn ignore(&mut self, dir: &Path) -> Result<Option<&Gitignore>> {
+        if !self.ignores.contains_key(dir) {
+            let path = dir.join(IGNORE_FILE);
+            let ignore = if path.is_file() {
+                let (ignore, error) = Gitignore::new(&path);
+                if let Some(e) = error {
+                    let e = io::Error::new(io::ErrorKind::InvalidData, e);
+                    return Err(GemAtomError::io(&path, e));
+                }
+                Some(ignore)
+            } else {
+                None
+            };
+            self.ignores.insert(dir.to_path_buf(), ignore);
+        }
+        Ok(self.ignores[dir].as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_is_excluded() {
+        let root = TempDir::new("exclude");
+        fs::create_dir_all(root.join("noise/spam")).unwrap();
+        fs::write(root.join(IGNORE_FILE), "*.wip.gmi\n").unwrap();
+        fs::write(
+            root.join("noise").join(IGNORE_FILE),
+            "spam/\n!keep.wip.gmi\n",
+        )
+        .unwrap();
+        let patterns = vec![
+            "texts/old-*".parse().unwrap(),
+            "regex:^texts/[0-9]+\\.gmi$".parse().unwrap(),
+        ];
+        let mut excluder = Excluder::new(&root, &patterns);
+        let mut excluded = |p: &str| excluder.is_excluded(&root.join(p)).unwrap();
+        assert!(excluded("texts/old-foo.gmi"));
+        assert!(excluded("texts/123.gmi"));
+        assert!(!excluded("texts/foo.gmi"));
+        assert!(excluded("texts/foo.wip.gmi"));
+        assert!(excluded("noise/spam/index.gmi"));
+        assert!(!excluded("noise/eggs/index.gmi"));
+        assert!(!excluded("noise/keep.wip.gmi"));
+        assert!("regex:(".parse::<Exclude>().is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Gemini subscription pages ("gemlogs"), as described in the
+//! "Subscribing to Gemini pages" companion specification: a level 1
+//! heading followed by link lines of the form
+//! `=> URL YYYY-MM-DD Title`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use atom_syndication::Feed;
+
+use crate::category::ArticleFiles;
+use crate::error::{GemAtomError, Result};
+use crate::util::{find_index, write_if_changed, Access};
+
+/// Default heading of the section of an index file rewritten by
+/// gematom. Gemtext has no comments, so the section is delimited by
+/// headings, which clients show as such.
+pub const SECTION_HEADING: &str = "## Gemlog";
+
+/// The link lines of a gemlog, one per entry of `feed`, dated by
+/// publication.
+pub fn render_links(feed: &Feed) -> String {
+    let mut links = String::new();
+    for entry in feed.entries() {
+        let href = match entry.links().first() {
+            Some(link) => link.href(),
+            None => entry.id(),
+        };
+        links.push_str(&format!(
+            "=> {} {} {}\n",
+            href,
+            entry
+                .published()
+                .unwrap_or_else(|| entry.updated())
+                .format("%Y-%m-%d"),
+            entry.title()
+        ));
+    }
+    links
+}
+
+/// A whole gemlog page for `feed`.
+pub fn render(feed: &Feed) -> String {
+    let mut page = format!("# {}\n", feed.title());
+    if let Some(subtitle) = feed.subtitle() {
+        page.push_str(&format!("## {}\n", subtitle));
+    }
+    page.push('\n');
+    page.push_str(&render_links(feed));
+    page
+}
+
+/// Write the gemlog page of `feed` to `path`, unless it is
+/// unchanged.
+pub fn write_gemlog(feed: &Feed, path: &Path) -> Result<()> {
+    write_if_changed(path, render(feed).as_bytes(), Access::Served, false).map(|_| ())
+}
+
+/// Replace the lines following the `heading` line of `text`, up to
+/// the next heading, with `links`. Returns `None` if there is no such
+/// heading.
+fn splice_section(text: &str, heading: &str, links: &str) -> Option<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let start = lines.iter().position(|l| l.trim() == heading.trim())? + 1;
+    let end = lines[start..]
+        .iter()
+        .position(|l| l.starts_with('#'))
+        .map_or(lines.len(), |n| start + n);
+    let mut result = String::new();
+    for line in &lines[..start] {
+        result.push_str(line);
+        result.push('\n');
+    }
+    result.push_str(links);
+    if end < lines.len() {
+        result.push('\n');
+    }
+    for line in &lines[end..] {
+        result.push_str(line);
+        result.push('\n');
+    }
+    Some(result)
+}
+
+/// Rewrite the section under `heading` of the index file of
+/// directory `dir`, named as in `files`, with the link lines of
+/// `feed`. Returns the path of the index file, or `None` if there is
+/// no index file, it has no such section, or it is unchanged.
+pub fn update_index(
+    feed: &Feed,
+    dir: &Path,
+    files: &ArticleFiles,
+    heading: &str,
+) -> Result<Option<PathBuf>> {
+    let path = match find_index(dir, &files.index) {
+        Some(p) => p,
+        None => return Ok(None),
+    };
+    let text = fs::read_to_string(&path).map_err(|e| GemAtomError::io(&path, e))?;
+    match splice_section(&text, heading, &render_links(feed)) {
+        Some(updated) => {
+            let written = write_if_changed(&path, updated.as_bytes(), Access::Served, false)?;
+            Ok(if written { Some(path) } else { None })
+        }
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+    use atom_syndication::{Entry, Link};
+
+    fn feed() -> Feed {
+        let mut entry = Entry::default();
+        entry.set_title("Spam & eggs");
+        entry.set_updated(
+            "2021-03-04T14:30:00+01:00"
+                .parse::<atom_syndication::FixedDateTime>()
+                .unwrap(),
+        );
+        let mut link = Link::default();
+        link.set_href("gemini://example.org/spam.gmi");
+        entry.set_links(vec![link]);
+        let mut feed = Feed::default();
+        feed.set_title("My gemlog");
+        feed.set_entries(vec![entry]);
+        feed
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render(&feed()),
+            "# My gemlog\n\n=> gemini://example.org/spam.gmi 2021-03-04 Spam & eggs\n"
+        );
+    }
+
+    #[test]
+    fn test_splice_section() {
+        let links = render_links(&feed());
+        let text = "# Index\n## Gemlog\n=> old.gmi\n\n## About\nfooter\n";
+        assert_eq!(
+            splice_section(text, SECTION_HEADING, &links),
+            Some(String::from(
+                "# Index\n## Gemlog\n=> gemini://example.org/spam.gmi 2021-03-04 Spam & eggs\n\n## About\nfooter\n"
+            ))
+        );
+        // up to the end of the file
+        assert_eq!(
+            splice_section("# Index\n### Posts \n=> old.gmi\n", "### Posts", &links),
+            Some(format!("# Index\n### Posts \n{}", links))
+        );
+        assert_eq!(splice_section("# Index\n", SECTION_HEADING, ""), None);
+    }
+
+    #[test]
+    fn test_update_index() {
+        let dir = TempDir::new("gemlog");
+        let text = format!("# Index\n{}\n", SECTION_HEADING);
+        fs::write(dir.join("index.gmi"), &text).unwrap();
+        fs::write(dir.join("README.gmi"), &text).unwrap();
+        let files = ArticleFiles {
+            index: vec![String::from("README.gmi")],
+            ..ArticleFiles::default()
+        };
+        let updated = update_index(&feed(), &dir, &files, SECTION_HEADING).unwrap();
+        assert_eq!(updated, Some(dir.join("README.gmi")));
+        // nothing to update on a rerun
+        let rerun = update_index(&feed(), &dir, &files, SECTION_HEADING).unwrap();
+        assert_eq!(rerun, None);
+        let readme = fs::read_to_string(dir.join("README.gmi")).unwrap();
+        assert!(readme.contains("=> gemini://example.org/spam.gmi"));
+        assert_eq!(fs::read_to_string(dir.join("index.gmi")).unwrap(), text);
+    }
+}
@@ -0,0 +1,238 @@
+//! A line-level gemtext parser.
+//!
+//! ```
+//! use gematom::gemtext::{Document, Line};
+//!
+//! let doc = Document::parse("```\n# not a title\n```\n## Title\n=> /foo.gmi Foo\n");
+//! assert_eq!(doc.first_heading(), Some("Title"));
+//! assert_eq!(doc.links().collect::<Vec<_>>(), vec![("/foo.gmi", Some("Foo"))]);
+//! ```
+
+/// A line of a gemtext document. Preformatted text is kept as a
+/// single block.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Line<'a> {
+    /// A text line, possibly empty.
+    Text(&'a str),
+    /// A link line: `=> URL [label]`.
+    Link {
+        url: &'a str,
+        label: Option<&'a str>,
+    },
+    /// A heading, of level 1 to 3.
+    Heading { level: u8, text: &'a str },
+    /// A list item: `* item`.
+    ListItem(&'a str),
+    /// A quote line: `> quote`.
+    Quote(&'a str),
+    /// A preformatted block, between two lines starting with "```".
+    /// The alt text is the rest of the opening line.
+    Preformatted {
+        alt: Option<&'a str>,
+        lines: Vec<&'a str>,
+    },
+}
+
+/// A parsed gemtext document, borrowing its text.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Document<'a> {
+    text: &'a str,
+    lines: Vec<Line<'a>>,
+    /// Offset in `text` of each line.
+    starts: Vec<usize>,
+}
+
+impl<'a> Document<'a> {
+    /// Parse a gemtext document. An unterminated preformatted block
+    /// extends to the end of the document.
+    pub fn parse(text: &'a str) -> Self {
+        let mut doc = Document {
+            text,
+            ..Document::default()
+        };
+        let mut lines = text.split_inclusive('\n').scan(0, |offset, raw| {
+            let start = *offset;
+            *offset += raw.len();
+            Some((start, strip_newline(raw)))
+        });
+        while let Some((start, line)) = lines.next() {
+            doc.starts.push(start);
+            if let Some(alt) = line.strip_prefix("```") {
+                let alt = alt.trim();
+                let block = lines
+                    .by_ref()
+                    .map(|(_, l)| l)
+                    .take_while(|l| !l.starts_with("```"))
+                    .collect();
+                doc.lines.push(Line::Preformatted {
+                    alt: if alt.is_empty() { None } else { Some(alt) },
+                    lines: block,
+                });
+            } else {
+                doc.lines.push(parse_line(line));
+            }
+        }
+        doc
+    }
+
+    /// The text of the document.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// The lines of the document.
+    pub fn lines(&self) -> &[Line<'a>] {
+        &self.lines
+    }
+
+    /// The text of the line at `idx`, a whole block for preformatted
+    /// text, along with its line endings.
+    pub fn source(&self, idx: usize) -> &'a str {
+        let end = self.starts.get(idx + 1).copied();
+        &self.text[self.starts[idx]..end.unwrap_or(self.text.len())]
+    }
+
+    /// The document made of the lines following the first `n` ones.
+    pub fn rest(&self, n: usize) -> Document<'a> {
+        let start = self.starts.get(n).copied().unwrap_or(self.text.len());
+        Document {
+            text: &self.text[start..],
+            lines: self.lines[n..].to_vec(),
+            starts: self.starts[n..].iter().map(|s| s - start).collect(),
+        }
+    }
+
+    /// The text of the first heading, whatever its level. Preformatted
+    /// text is ignored.
+    pub fn first_heading(&self) -> Option<&'a str> {
+        self.lines.iter().find_map(|l| match l {
+            Line::Heading { text, .. } => Some(*text),
+            _ => None,
+        })
+    }
+
+    /// The lines following the first heading, or all lines if there
+    /// is no heading.
+    pub fn after_first_heading(&self) -> &[Line<'a>] {
+        match self
+            .lines
+            .iter()
+            .position(|l| matches!(l, Line::Heading { .. }))
+        {
+            Some(idx) => &self.lines[idx + 1..],
+            None => &self.lines,
+        }
+    }
+
+    /// URL and label of the link lines.
+    pub fn links(&self) -> impl Iterator<Item = (&'a str, Option<&'a str>)> + '_ {
+        self.lines.iter().filter_map(|l| match l {
+            Line::Link { url, label } => Some((*url, *label)),
+            _ => None,
+        })
+    }
+}
+
+/// Strip the line ending, `\n` or `\r\n`, off a line.
+fn strip_newline(raw: &str) -> &str {
+    match raw.strip_suffix('\n') {
+        Some(line) => line.strip_suffix('\r').unwrap_or(line),
+        None => raw,
+    }
+}
+
+/// Parse a line found outside of a preformatted block.
+fn parse_line(line: &str) -> Line<'_> {
+    if let Some(link) = line.strip_prefix("=>") {
+        let link = link.trim();
+        match link.find(char::is_whitespace) {
+            Some(idx) => Line::Link {
+                url: &link[..idx],
+                label: Some(link[idx..].trim()),
+            },
+            None => Line::Link {
+                url: link,
+                label: None,
+            },
+        }
+    } else if line.starts_with('#') {
+        let level = line.chars().take_while(|c| *c == '#').count().min(3) as u8;
+        Line::Heading {
+            level,
+            text: line.trim_start_matches('#').trim(),
+        }
+    } else if let Some(item) = line.strip_prefix("* ") {
+        Line::ListItem(item.trim())
+    } else if let Some(quote) = line.strip_prefix('>') {
+        Line::Quote(quote.trim())
+    } else {
+        Line::Text(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "# Title\n\nSome text\n=> /spam.gmi  Spam and eggs\n=>gemini://example.org/\n\
+                    * item\n> quote\n```python code\n# not a heading\n```\n### Sub\n```\nopen";
+        assert_eq!(
+            Document::parse(text).lines(),
+            vec![
+                Line::Heading {
+                    level: 1,
+                    text: "Title"
+                },
+                Line::Text(""),
+                Line::Text("Some text"),
+                Line::Link {
+                    url: "/spam.gmi",
+                    label: Some("Spam and eggs")
+                },
+                Line::Link {
+                    url: "gemini://example.org/",
+                    label: None
+                },
+                Line::ListItem("item"),
+                Line::Quote("quote"),
+                Line::Preformatted {
+                    alt: Some("python code"),
+                    lines: vec!["# not a heading"]
+                },
+                Line::Heading {
+                    level: 3,
+                    text: "Sub"
+                },
+                Line::Preformatted {
+                    alt: None,
+                    lines: vec!["open"]
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let doc = Document::parse("intro\n```\n# code\n```\n## Title\ntext\n=> a.gmi\n");
+        assert_eq!(doc.first_heading(), Some("Title"));
+        assert_eq!(doc.after_first_heading()[0], Line::Text("text"));
+        assert_eq!(doc.links().collect::<Vec<_>>(), vec![("a.gmi", None)]);
+        let doc = Document::parse("no heading\n");
+        assert_eq!(doc.first_heading(), None);
+        assert_eq!(doc.after_first_heading().len(), 1);
+    }
+
+    #[test]
+    fn test_rest() {
+        let doc = Document::parse("```\nblock\n```\r\n# Title\n\ntext");
+        assert_eq!(doc.source(0), "```\nblock\n```\r\n");
+        assert_eq!(doc.source(3), "text");
+        let rest = doc.rest(1);
+        assert_eq!(rest.text(), "# Title\n\ntext");
+        assert_eq!(rest.lines(), &doc.lines()[1..]);
+        assert_eq!(rest.source(1), "\n");
+        assert_eq!(doc.rest(4), Document::parse(""));
+    }
+}
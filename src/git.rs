@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use git2::{Repository, Sort};
+
+use crate::error::{GemAtomError, Result};
+
+/// First and last commit times of the files of a git repository, in
+/// seconds since the epoch.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct History {
+    times: HashMap<PathBuf, (i64, i64)>,
+}
+
+impl History {
+    /// Read the history of the repository containing `dir`, from its
+    /// HEAD. Renamed files are dated from their renaming.
+    pub fn read(dir: &Path) -> Result<History> {
+        let error = |e| GemAtomError::Git {
+            path: dir.to_path_buf(),
+            source: e,
+        };
+        let repo = Repository::discover(dir).map_err(error)?;
+        let workdir = match repo.workdir() {
+            Some(w) => fs::canonicalize(w).map_err(|e| GemAtomError::io(w, e))?,
+            None => return Err(error(git2::Error::from_str("bare repository"))),
+        };
+        let mut walk = repo.revwalk().map_err(error)?;
+        walk.set_sorting(Sort::TIME).map_err(error)?;
+        walk.push_head().map_err(error)?;
+        let mut times: HashMap<PathBuf, (i64, i64)> = HashMap::new();
+        for oid in walk {
+            let commit = repo.find_commit(oid.map_err(error)?).map_err(error)?;
+            let time = commit.time().seconds();
+            let tree = commit.tree().map_err(error)?;
+            // merges are compared to their first parent only
+            let parent = match commit.parents().next() {
+                Some(p) => Some(p.tree().map_err(error)?),
+                None => None,
+            };
+            let diff = repo
+                .diff_tree_to_tree(parent.as_ref(), Some(&tree), None)
+                .map_err(error)?;
+            for delta in diff.deltas() {
+                if let Some(path) = delta.new_file().path() {
+                    let t = times.entry(workdir.join(path)).or_insert((time, time));
+                    *t = (t.0.min(time), t.1.max(time));
+                }
+            }
+        }
+        Ok(History { times })
+    }
+
+    /// First and last commit times of the file at `path`, if it was
+    /// ever committed.
+    pub fn times(&self, path: &str) -> Option<(i64, i64)> {
+        let path = fs::canonicalize(path).ok()?;
+        self.times.get(&path).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+    use git2::{Signature, Time};
+
+    #[test]
+    fn test_history() {
+        let root = TempDir::new("git");
+        let repo = Repository::init(&root).unwrap();
+        let mut parent = None;
+        for (secs, content) in &[(1_000_000, "# A\n"), (2_000_000, "# B\n")] {
+            fs::write(root.join("a.gmi"), content).unwrap();
+            let mut index = repo.index().unwrap();
+            index.add_path(Path::new("a.gmi")).unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let sig = Signature::new("me", "me@example.org", &Time::new(*secs, 0)).unwrap();
+            let parents: Vec<_> = parent.iter().collect();
+            let oid = repo
+                .commit(Some("HEAD"), &sig, &sig, "commit", &tree, &parents)
+                .unwrap();
+            parent = Some(repo.find_commit(oid).unwrap());
+        }
+        fs::write(root.join("b.gmi"), "# B\n").unwrap();
+
+        let history = History::read(&root).unwrap();
+        let a = root.join("a.gmi");
+        assert_eq!(
+            history.times(a.to_str().unwrap()),
+            Some((1_000_000, 2_000_000))
+        );
+        let b = root.join("b.gmi");
+        assert_eq!(history.times(b.to_str().unwrap()), None);
+    }
+}
@@ -0,0 +1,99 @@
+//! Rendering of gemtext as HTML.
+
+use url::Url;
+
+use crate::gemtext::Line;
+use crate::util::xml_escape;
+
+/// Render gemtext lines as XHTML markup, which is also valid HTML.
+/// Relative links are resolved against `base`.
+pub(crate) fn render(lines: &[Line], base: &Url) -> String {
+    let mut html = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        match &lines[i] {
+            Line::Text(text) => {
+                if !text.trim().is_empty() {
+                    html.push_str(&format!("<p>{}</p>\n", xml_escape(text)));
+                }
+            }
+            Line::Link { url, label } => {
+                let href = match base.join(url) {
+                    Ok(u) => u.to_string(),
+                    Err(_) => url.to_string(),
+                };
+                html.push_str(&format!(
+                    "<p><a href=\"{}\">{}</a></p>\n",
+                    xml_escape(&href),
+                    xml_escape(label.unwrap_or(url))
+                ));
+            }
+            Line::Heading { level, text } => {
+                html.push_str(&format!("<h{0}>{1}</h{0}>\n", level, xml_escape(text)));
+            }
+            Line::ListItem(_) => {
+                html.push_str("<ul>\n");
+                while let Some(Line::ListItem(item)) = lines.get(i) {
+                    html.push_str(&format!("<li>{}</li>\n", xml_escape(item)));
+                    i += 1;
+                }
+                html.push_str("</ul>\n");
+                continue;
+            }
+            Line::Quote(_) => {
+                html.push_str("<blockquote>\n");
+                while let Some(Line::Quote(quote)) = lines.get(i) {
+                    html.push_str(&format!("<p>{}</p>\n", xml_escape(quote)));
+                    i += 1;
+                }
+                html.push_str("</blockquote>\n");
+                continue;
+            }
+            Line::Preformatted { alt, lines: block } => {
+                match alt {
+                    Some(alt) => html.push_str(&format!("<pre title=\"{}\">", xml_escape(alt))),
+                    None => html.push_str("<pre>"),
+                }
+                for line in block {
+                    html.push_str(&xml_escape(line));
+                    html.push('\n');
+                }
+                html.push_str("</pre>\n");
+            }
+        }
+        i += 1;
+    }
+    html
+}
+
+/// Render gemtext lines as an XHTML `div`, suitable for an atom
+/// content of type `xhtml`.
+pub(crate) fn render_xhtml_div(lines: &[Line], base: &Url) -> String {
+    format!(
+        "<div xmlns=\"http://www.w3.org/1999/xhtml\">\n{}</div>",
+        render(lines, base)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::Document;
+
+    #[test]
+    fn test_render() {
+        let base = Url::parse("gemini://example.org/texts/foo.gmi").unwrap();
+        let text = "# Spam & eggs\n\nSome <text>\n=> bar.gmi Bar\n=> gemini://other.org/\n\
+                    * one\n* two\n> a\n> b\n```sh\n$ ls\n```\n";
+        assert_eq!(
+            render(Document::parse(text).lines(), &base),
+            "<h1>Spam &amp; eggs</h1>\n\
+             <p>Some &lt;text&gt;</p>\n\
+             <p><a href=\"gemini://example.org/texts/bar.gmi\">Bar</a></p>\n\
+             <p><a href=\"gemini://other.org/\">gemini://other.org/</a></p>\n\
+             <ul>\n<li>one</li>\n<li>two</li>\n</ul>\n\
+             <blockquote>\n<p>a</p>\n<p>b</p>\n</blockquote>\n\
+             <pre title=\"sh\">$ ls\n</pre>\n"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use std::str::FromStr;
+
+use atom_syndication::FixedDateTime;
+use serde::Deserialize;
+use url::Url;
+
+/// How the id of a new entry is made. Entries keep the id they were
+/// first given as long as they are known to the state cache, and an
+/// id given in the article metadata always wins.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdScheme {
+    /// The URL of the entry.
+    Url,
+    /// A tag URI (RFC 4151) made of the tag authority, the date of
+    /// the article and the path the entry was first served from.
+    Tag,
+}
+
+impl FromStr for IdScheme {
+    type Err = String;
+
+    /// Build an id scheme from a string.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "url" => Ok(IdScheme::Url),
+            "tag" => Ok(IdScheme::Tag),
+            _ => Err(format!("Invalid id scheme {}", s)),
+        }
+    }
+}
+
+impl IdScheme {
+    /// Id of the entry served from `url`, dated `date`. Tag URIs are
+    /// minted by `authority`, such as `example.org,2021`, or else by
+    /// the host of `url` on `date`; URLs without a host are kept as
+    /// ids.
+    pub(crate) fn id(self, authority: Option<&str>, url: &Url, date: &FixedDateTime) -> String {
+        let day = date.format("%Y-%m-%d");
+        match (self, authority, url.host_str()) {
+            (IdScheme::Tag, Some(authority), _) => {
+                format!("tag:{}:{}{}", authority, day, url.path())
+            }
+            (IdScheme::Tag, None, Some(host)) => format!("tag:{},{}:{}", host, day, url.path()),
+            _ => url.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id() {
+        let url = Url::parse("gemini://example.org/texts/foo.gmi").unwrap();
+        let date = FixedDateTime::parse_from_rfc3339("2021-03-04T23:30:00-02:00").unwrap();
+        assert_eq!(
+            IdScheme::Tag.id(None, &url, &date),
+            "tag:example.org,2021-03-04:/texts/foo.gmi"
+        );
+        assert_eq!(
+            IdScheme::Tag.id(Some("example.net,2020"), &url, &date),
+            "tag:example.net,2020:2021-03-04/texts/foo.gmi"
+        );
+        assert_eq!(IdScheme::Url.id(None, &url, &date), url.as_str());
+    }
+}
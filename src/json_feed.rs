@@ -0,0 +1,148 @@
+use atom_syndication::{Entry, Feed, Person};
+use serde::Serialize;
+use url::Url;
+
+use crate::rss_feed::link_href;
+
+/// Version URL of the JSON Feed specification implemented.
+const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// A JSON Feed 1.1 document.
+#[derive(Serialize)]
+pub(crate) struct JsonFeed {
+    version: &'static str,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    home_page_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    feed_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<Author>,
+    items: Vec<Item>,
+}
+
+#[derive(Serialize)]
+struct Author {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Item {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_html: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_published: Option<String>,
+    date_modified: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<Author>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+}
+
+fn to_author(person: &Person) -> Author {
+    Author {
+        name: person.name().to_string(),
+        url: person
+            .uri()
+            .map(String::from)
+            .or_else(|| person.email().map(|e| format!("mailto:{}", e))),
+    }
+}
+
+/// Convert an atom entry into a JSON Feed item. Gemini content is
+/// kept as text, HTML and XHTML content as HTML.
+fn to_item(entry: &Entry) -> Item {
+    let (mut content_text, mut content_html) = (None, None);
+    if let Some(content) = entry.content() {
+        let value = content.value().map(String::from);
+        match content.content_type() {
+            Some("html") | Some("xhtml") => content_html = value,
+            _ => content_text = value,
+        }
+    }
+    Item {
+        id: entry.id().to_string(),
+        url: link_href(entry.links(), "alternate").map(String::from),
+        title: entry.title().to_string(),
+        summary: entry.summary().map(|s| s.to_string()),
+        content_text,
+        content_html,
+        date_published: entry.published().map(|d| d.to_rfc3339()),
+        date_modified: entry.updated().to_rfc3339(),
+        authors: entry.authors().iter().map(to_author).collect(),
+        tags: entry
+            .categories()
+            .iter()
+            .map(|c| c.term().to_string())
+            .collect(),
+    }
+}
+
+/// Convert an atom feed into a JSON Feed document, served from
+/// `feed_url` if known.
+pub(crate) fn to_json_feed(feed: &Feed, feed_url: Option<&Url>) -> JsonFeed {
+    let home_page_url = link_href(feed.links(), "alternate");
+    JsonFeed {
+        version: VERSION,
+        title: feed.title().to_string(),
+        home_page_url: home_page_url.map(String::from),
+        feed_url: feed_url.map(|u| u.to_string()),
+        description: feed.subtitle().map(|s| s.to_string()),
+        authors: feed.authors().iter().map(to_author).collect(),
+        items: feed.entries().iter().map(to_item).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atom_syndication::{Content, FixedDateTime, Link};
+
+    #[test]
+    fn test_to_json_feed() {
+        let mut entry = Entry::default();
+        entry.set_id("gemini://example.org/a.gmi");
+        entry.set_title("A");
+        entry.set_updated(FixedDateTime::parse_from_rfc3339("2021-03-04T00:00:00Z").unwrap());
+        let mut content = Content::default();
+        content.set_value("# A".to_string());
+        content.set_content_type("text/gemini".to_string());
+        entry.set_content(content);
+        let mut feed = Feed::default();
+        feed.set_title("Site");
+        let mut link = Link::default();
+        link.set_href("gemini://example.org/texts/");
+        link.set_rel("alternate");
+        feed.set_links(vec![link]);
+        let mut person = Person::default();
+        person.set_name("Eric");
+        person.set_email(Some("eric@example.org".to_string()));
+        feed.set_authors(vec![person]);
+        feed.set_entries(vec![entry]);
+
+        let url = Url::parse("gemini://example.org/texts/feed.json").unwrap();
+        let json = serde_json::to_value(to_json_feed(&feed, Some(&url))).unwrap();
+        assert_eq!(json["version"], VERSION);
+        assert_eq!(json["home_page_url"], "gemini://example.org/texts/");
+        assert_eq!(json["feed_url"], "gemini://example.org/texts/feed.json");
+        assert_eq!(json["authors"][0]["url"], "mailto:eric@example.org");
+        let item = &json["items"][0];
+        assert_eq!(item["id"], "gemini://example.org/a.gmi");
+        assert_eq!(item["content_text"], "# A");
+        assert_eq!(item["date_modified"], "2021-03-04T00:00:00+00:00");
+        assert!(item.get("content_html").is_none());
+        assert!(item.get("date_published").is_none());
+    }
+}
@@ -0,0 +1,67 @@
+//! GemAtom is an atom feed generator for Gemini sites.
+//!
+//! The main entry point is [`FeedBuilder`], which walks the
+//! categories of a site and returns an [`atom_syndication::Feed`].
+//! Its texts are not XML-escaped: write it with [`write_feed`] or
+//! [`Format::write`], which escape them, rather than with the
+//! serialization of atom_syndication.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use gematom::{write_feed, Category, FeedBuilder, TimeSource};
+//! use url::Url;
+//!
+//! let base = Url::parse("gemini://example.org/").unwrap();
+//! let feed = FeedBuilder::new("/var/gemini/space", base)
+//!     .category("texts", Category::Flat)
+//!     .category("noise", Category::Tree)
+//!     .time_source(TimeSource::Mtime)
+//!     .max_entries(20)
+//!     .build()?;
+//! write_feed(&feed, Path::new("/var/gemini/space/atom.xml"))?;
+//! # Ok::<(), gematom::GemAtomError>(())
+//! ```
+
+extern crate atom_syndication;
+extern crate glob;
+extern crate regex;
+extern crate url;
+#[macro_use]
+extern crate lazy_static;
+
+mod builder;
+mod category;
+mod config;
+mod content;
+mod date;
+pub mod diff;
+mod error;
+mod exclude;
+pub mod gemlog;
+pub mod gemtext;
+mod git;
+mod html;
+mod id;
+mod json_feed;
+mod meta;
+mod output;
+mod rss_feed;
+mod state;
+mod util;
+pub mod watch;
+
+pub use builder::{FeedBuilder, GeneratedFeed};
+pub use category::{detect_categories, ArticleFiles, Category, TreeDepth, ROOT_CATEGORY};
+pub use config::{write_categories, CategoryConfig, Config, CONFIG_FILE};
+pub use content::ContentType;
+pub use date::{parse_timezone, SortBy, TimeSource};
+pub use error::{Diagnostic, GemAtomError, Result};
+pub use exclude::{Exclude, IGNORE_FILE};
+pub use id::IdScheme;
+pub use output::{read_feed, write_feed, Format};
+pub use state::STATE_FILE;
+pub use util::parse_gemini_url;
+
+/// Version of gematom, as advertised in the feed generator element.
+pub const VERSION: &str = "1.1.1";
@@ -1,68 +1,29 @@
-extern crate atom_syndication;
-extern crate glob;
-extern crate regex;
-extern crate url;
 #[macro_use]
 extern crate clap;
-#[macro_use]
-extern crate lazy_static;
 
-use std::collections::HashMap;
-use std::convert::TryInto;
 use std::fs;
-use std::io::prelude::*;
-use std::io::BufReader;
-use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::time;
-
-use atom_syndication::{Entry, Feed, FixedDateTime, Generator, Link, Person};
-use chrono::prelude::*;
-use chrono::NaiveDateTime;
-use clap::{App, Arg, Values};
-use glob::glob;
-use pathdiff::diff_paths;
-use regex::Regex;
+use std::process;
+use std::time::Duration;
+
+use atom_syndication::Feed;
+use clap::{App, Arg, ArgMatches, SubCommand, Values};
+use gematom::watch::Watcher;
+use gematom::{
+    detect_categories, diff, gemlog, parse_gemini_url, parse_timezone, read_feed, CategoryConfig,
+    Config, Exclude, FeedBuilder, Format, GemAtomError, SortBy, TimeSource, ROOT_CATEGORY, VERSION,
+};
 use url::Url;
 
-const VERSION: &str = "1.1.1";
-
-lazy_static! {
-    static ref RFC3339_RE: Regex = Regex::new(r"^\d{4}-\d{2}-\d{2}").unwrap();
-}
-
-
-/// Categories
-#[derive(PartialEq, Copy, Clone, Debug)]
-enum Category {
-    FLAT,
-    TREE,
-}
-
-impl Category {
-    /// Build a category from a string.
-    fn from_str(s: &str) -> Result<Self, String> {
-        match s {
-            "flat" => Ok(Category::FLAT),
-            "tree" => Ok(Category::TREE),
-            _ => Err(format!("Invalid category {}", s)),
-        }
-    }
-}
-
-// useful when collecting files
-#[derive(Clone)]
-struct Pair(String, Category);
-
 /// Checks if a category option is well formed.
 /// Called by clap.
 fn is_category(val: String) -> Result<(), String> {
     let v: Vec<&str> = val.split(':').collect();
     if v.len() != 2 {
-        return Err(String::from(format!("Bad category specification: {}", val)));
+        return Err(format!("Bad category specification: {}", val));
     };
-    if !["flat", "tree"].contains(&v[1]) {
-        return Err(String::from(format!("Not a valid category: {}", &v[1])));
+    if !["flat", "tree", "dated"].contains(&v[1]) {
+        return Err(format!("Not a valid category: {}", &v[1]));
     };
     Ok(())
 }
@@ -70,17 +31,13 @@ fn is_category(val: String) -> Result<(), String> {
 /// Check if string is a valid gemini url.
 /// Called by clap.
 fn is_gemini_url(val: String) -> Result<(), String> {
-    let url = match Url::parse(&val) {
-        Ok(u) => u,
-        Err(e) => return Err(format!("{}", e)),
-    };
-    if url.scheme() != "gemini" {
-        return Err(format!("Bad url scheme : {}", url.scheme()));
-    };
-    if url.username() != "" || url.password() != None {
-        return Err(format!("user authentication not allowed in url {}", val));
-    };
-    return Ok(());
+    parse_gemini_url(&val).map(|_| ())
+}
+
+/// Check if string is a valid timezone offset.
+/// Called by clap.
+fn is_timezone(val: String) -> Result<(), String> {
+    parse_timezone(&val).map(|_| ())
 }
 
 /// Check if a pathname is an existing directory
@@ -91,505 +48,593 @@ fn is_valid_directory(val: String) -> Result<(), String> {
             if meta.is_dir() {
                 Ok(())
             } else {
-                Err(String::from(format!("Invalid directory: {}", &val)))
+                Err(format!("Invalid directory: {}", &val))
             }
         }
-        Err(_) => Err(String::from(format!("Invalid directory: {}", &val))),
-    }
-}
-
-/// Returns true if val is a regular file.
-fn is_file(val: &str) -> bool {
-    match fs::metadata(val) {
-        Ok(meta) => meta.is_file(),
-        _ => false,
-    }
-}
-
-/// Returns true if val is a world readable pathname.
-fn is_world_readable(val: &str) -> bool {
-    match fs::metadata(val) {
-        Ok(meta) => (meta.permissions().mode() & 0o4) != 0,
-        _ => false,
+        Err(_) => Err(format!("Invalid directory: {}", &val)),
     }
 }
 
 /// Parses the list of categories given as options.
-fn parse_categories(values: &mut Values) -> Result<HashMap<String, Category>, String> {
-    let mut cats = HashMap::new();
+fn parse_categories(values: &mut Values) -> Result<Vec<CategoryConfig>, String> {
+    let mut cats = Vec::new();
     for value in values {
         let v: Vec<&str> = value.split(':').collect();
-        cats.insert(v[0].to_string(), Category::from_str(v[1])?);
+        cats.push(CategoryConfig::new(v[0], v[1].parse()?));
     }
     Ok(cats)
 }
 
-/// Returns the last modification time of a file.
-fn mtime(fname: &str) -> time::SystemTime {
-    fs::metadata(fname).unwrap().modified().unwrap()
-}
-
-/// Returns the last change time of a file.
-fn ctime(fname: &str) -> time::SystemTime {
-    fs::metadata(fname).unwrap().created().unwrap()
-}
-
-/// Collect all articles in a category.
-fn collect_articles(name: &str, typ: Category, root: &str) -> Vec<Pair> {
-    let mut fulldir = PathBuf::new();
-    fulldir.push(root);
-    fulldir.push(name);
-    let globs = match typ {
-        Category::FLAT => vec!["*.gmi", "*.gemini"],
-        Category::TREE => vec!["**/*.gmi", "**/*.gemini"],
-    };
-    let mut articles = Vec::new();
-    let indexes = vec!["index.gmi", "index.gemini"];
-    for pat in globs {
-        let mut fullpattern = fulldir.clone();
-        fullpattern.push(pat);
-        for path in glob(fullpattern.as_path().to_str().unwrap()).unwrap() {
-            match path {
-                Ok(path) => {
-                    let fname = path.file_name().unwrap().to_str().unwrap();
-                    if ((!indexes.contains(&fname)) && typ == Category::FLAT)
-                        || (indexes.contains(&fname)
-                            && typ == Category::TREE
-                            && diff_paths(path.as_path(), fulldir.as_path().to_str().unwrap())
-                                .unwrap()
-                                .as_path()
-                                .to_str()
-                                .unwrap()
-                                .contains('/'))
-                    {
-                        articles.push(Pair(String::from(path.as_path().to_str().unwrap()), typ));
-                    }
-                }
-                _ => {}
-            }
-        }
-    }
-    let articles = articles
-        .iter()
-        .cloned()
-        .filter(|e| {
-            let Pair(f, _) = e;
-            is_world_readable(f)
-        })
-        .collect();
-    return articles;
-}
-
-/// Extract the first gemini heading in a file. If no such heading is
-/// found, return a default string.
-///
-/// No check is made concerning the existence of the file.
-fn extract_first_heading(filename: &str, default: &str) -> String {
-    let f = fs::File::open(filename).unwrap();
-    let reader = BufReader::new(f);
-    for line in reader.lines() {
-        let line = line.unwrap();
-        let mut buf = &line[..];
-        if buf.starts_with("#") {
-            while buf.chars().nth(0).unwrap() == '#' {
-                buf = &buf[1..];
-            }
-            return String::from(buf.trim());
-        }
+/// Returns the value of a numeric option, if present.
+fn usize_option(matches: &ArgMatches, name: &str) -> Result<Option<usize>, String> {
+    if matches.is_present(name) {
+        Ok(Some(value_t!(matches, name, usize).map_err(|e| e.message)?))
+    } else {
+        Ok(None)
     }
-    return String::from(default);
 }
 
-/// Get the feed title.
-///
-/// If there is an index file, try to extract the first heading,
-/// otherwise use the directory name.
-fn get_feed_title(dir: &str, clean: bool) -> String {
-    let d = Path::new(dir);
-    let default = d.file_name().unwrap().to_str().unwrap();
-    let default = if clean {
-        default.replace("_", " ")
-    } else {
-        default.to_string()
+/// Builds a configuration out of the command line options.
+fn config_from_matches(matches: &ArgMatches) -> Result<Config, String> {
+    let base = match matches.value_of("base") {
+        Some(b) => Some(parse_gemini_url(b)?),
+        None => None,
     };
-    for index_file in vec!["index.gemini", "index.gmi"] {
-        let mut index_path = PathBuf::new();
-        index_path.push(dir);
-        index_path.push(index_file);
-        let index_path = index_path.to_str().unwrap();
-        if is_file(index_path) && is_world_readable(index_path) {
-            return extract_first_heading(index_path, &default);
+    let categories = match matches.values_of("category") {
+        Some(mut values) => parse_categories(&mut values)?,
+        None => Vec::new(),
+    };
+    let content = match matches.value_of("content") {
+        Some(c) => Some(c.parse()?),
+        None => None,
+    };
+    let sort_by: Option<SortBy> = match matches.value_of("sort-by") {
+        Some(s) => Some(s.parse()?),
+        None => None,
+    };
+    let id_scheme = match matches.value_of("id-scheme") {
+        Some(i) => Some(i.parse()?),
+        None => None,
+    };
+    let exclude = match matches.values_of("exclude") {
+        Some(values) => Some(
+            values
+                .map(str::parse)
+                .collect::<Result<Vec<Exclude>, String>>()?,
+        ),
+        None => None,
+    };
+    let timezone = match matches.value_of("timezone") {
+        Some(tz) => Some(parse_timezone(tz)?),
+        None => None,
+    };
+    let format = match matches.values_of("format") {
+        Some(values) => Some(
+            values
+                .map(str::parse)
+                .collect::<Result<Vec<Format>, String>>()?,
+        ),
+        None => None,
+    };
+    let flag = |name| {
+        if matches.is_present(name) {
+            Some(true)
+        } else {
+            None
         }
-    }
-    return default.to_string();
+    };
+    let time_source = match matches.value_of("time-source") {
+        Some(t) => Some(t.parse()?),
+        None => flag("mtime").map(|_| TimeSource::Mtime),
+    };
+    Ok(Config {
+        directory: matches.value_of("directory").map(PathBuf::from),
+        base,
+        categories,
+        auto_categories: flag("auto-categories"),
+        output: matches.value_of("output").map(String::from),
+        entries: usize_option(matches, "n")?,
+        title: matches.value_of("title").map(String::from),
+        subtitle: matches.value_of("subtitle").map(String::from),
+        author: matches.value_of("author").map(String::from),
+        email: matches.value_of("email").map(String::from),
+        clean_title: flag("clean-title"),
+        time_source,
+        sort_by,
+        id_scheme,
+        tag_authority: matches.value_of("tag-authority").map(String::from),
+        exclude,
+        timezone,
+        summary: usize_option(matches, "summary")?,
+        content,
+        max_length: usize_option(matches, "max-length")?,
+        strict: flag("strict"),
+        per_category: flag("per-category"),
+        incremental: flag("incremental"),
+        gemlog: matches.value_of("gemlog").map(String::from),
+        gemlog_index: flag("gemlog-index"),
+        gemlog_heading: matches.value_of("gemlog-heading").map(String::from),
+        backup: flag("backup"),
+        format,
+    })
 }
 
-/// Extract the files in the specified `categories`, starting from
-/// `root` directory. Use `time_func` for sorting. Keep `n` files.
-fn get_files(
-    root: &str,
-    categories: &HashMap<String, Category>,
-    time_func: fn(&str) -> time::SystemTime,
-    n: usize,
-) -> Option<Vec<Pair>> {
-    let mut files = Vec::new();
-    for (cat, typ) in categories {
-        files.extend(collect_articles(cat, *typ, root))
-    }
-    files.sort_by_key(|a| {
-        let Pair(f, _) = a;
-        time_func(f)
-            .duration_since(time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
-    });
-    files.reverse();
-    if files.len() == 0 {
-        None
-    } else {
-        Some(Vec::from(&files[0..n]))
-    }
+/// Report a command line error and exit.
+fn usage_error(msg: &str) -> ! {
+    eprintln!("gematom: {}", msg);
+    process::exit(1);
 }
 
-/// Get the update time of a file.
-///
-/// If the file is in a flat category, then, if the name starts with
-/// a rfc3339 date, use it, otherwise use the `time_func`.  If the
-/// file is in a tree category, then it is an "index" file. If the
-/// parent dir name starts with an rfc3339 date, then use it,
-/// otherwise une the `time_func` on the file.
-fn get_update_time(
-    filepath: &str,
-    time_func: fn(&str) -> time::SystemTime,
-    cat: Category,
-) -> FixedDateTime {
-    let path = Path::new(filepath);
-    let basename = match cat {
-        Category::FLAT => path.file_name().unwrap().to_str().unwrap(),
-        Category::TREE => path
-            .parent()
-            .unwrap()
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap(),
-    };
-    if RFC3339_RE.is_match(basename) {
-        let date = format!("{}{}", &basename[0..10], "T00:00:00 Z");
-        return (&date).parse().unwrap();
-    }
-    let updated = time_func(filepath);
-    return FixedDateTime::from_utc(
-        NaiveDateTime::from_timestamp(
-            updated
-                .duration_since(time::UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-                .try_into()
-                .unwrap(),
-            0,
-        ),
-        FixedOffset::east(0),
-    );
+/// Exit code of a dry run which found changes.
+const DIFFERENCES: i32 = 9;
+
+/// Report an error and exit with the matching exit code.
+fn fail(e: GemAtomError) -> ! {
+    eprintln!("gematom: {}", e);
+    process::exit(e.exit_code());
 }
 
-/// Remove the rfc3339 date in front of a file name if present. If the
-/// next chars after the date are '-', '_' or a space, skip them.
-fn remove_rfc3339_date(filename: &str) -> &str {
-    if RFC3339_RE.is_match(filename) {
-        let mut char_indices = filename[10..].char_indices();
-        let idx = loop {
-            if let Some((idx, ch)) = char_indices.next() {
-                if !("_-".contains(ch) || ch.is_whitespace()) {
-                    break 10 + idx;
-                }
-            } else {
-                break 10;
-            }
-        };
-        &filename[idx..]
-    } else {
-        filename
-    }
+/// Arguments describing the site and its feeds, shared by all
+/// subcommands.
+fn site_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("author")
+            .short("a")
+            .long("author")
+            .value_name("NAME")
+            .help("Author name")
+            .takes_value(true),
+        Arg::with_name("auto-categories")
+            .long("auto-categories")
+            .help("Also detect the categories among the subdirs of the site, printing them"),
+        Arg::with_name("backup")
+            .long("backup")
+            .help("Keep the previous version of each feed file with a .bak suffix"),
+        Arg::with_name("base")
+            .short("b")
+            .long("base")
+            .value_name("URL")
+            .help("Base URL for feed and entries")
+            .validator(is_gemini_url)
+            .takes_value(true),
+        Arg::with_name("category")
+            .short("c")
+            .long("category")
+            .value_name("DIR:TYPE")
+            .help("Category of a subdir, or of the root dir '.'. 'flat', 'tree' ou 'dated'")
+            .multiple(true)
+            .validator(is_category)
+            .takes_value(true),
+        Arg::with_name("clean-title")
+            .short("C")
+            .long("clean-title")
+            .help("When using a file or directory name as a title, convert '_' into space."),
+        Arg::with_name("config")
+            .long("config")
+            .value_name("FILE")
+            .help("Configuration file (default DIR/gematom.toml, if present)")
+            .takes_value(true),
+        Arg::with_name("content")
+            .long("content")
+            .value_name("TYPE")
+            .help("Include articles as entry content, as 'gemini' text, 'html' or 'xhtml'")
+            .possible_values(&["gemini", "html", "xhtml"])
+            .takes_value(true),
+        Arg::with_name("directory")
+            .short("d")
+            .long("directory")
+            .value_name("DIR")
+            .help("Root directory of the site")
+            .validator(is_valid_directory)
+            .takes_value(true),
+        Arg::with_name("dry-run")
+            .long("dry-run")
+            .help("Only print how the written atom feeds would change, exiting with 9 if they would"),
+        Arg::with_name("email")
+            .short("e")
+            .long("email")
+            .value_name("EMAIL")
+            .help("author's email address")
+            .takes_value(true),
+        Arg::with_name("exclude")
+            .long("exclude")
+            .value_name("PATTERN")
+            .help("Leave out the articles whose path matches a glob, or a regex: prefixed regular expression. May be repeated")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true),
+        Arg::with_name("format")
+            .short("f")
+            .long("format")
+            .value_name("FORMAT")
+            .help("Feed format, 'atom', 'rss' or 'jsonfeed' (default atom). May be repeated")
+            .possible_values(&["atom", "rss", "jsonfeed"])
+            .multiple(true)
+            .number_of_values(1)
+            .use_delimiter(true)
+            .takes_value(true),
+        Arg::with_name("gemlog")
+            .long("gemlog")
+            .value_name("FILE")
+            .help("Also write a gemini subscription page named FILE next to each feed")
+            .takes_value(true),
+        Arg::with_name("gemlog-heading")
+            .long("gemlog-heading")
+            .value_name("LINE")
+            .help("Heading of the index file section rewritten by --gemlog-index (default '## Gemlog')")
+            .takes_value(true),
+        Arg::with_name("gemlog-index")
+            .long("gemlog-index")
+            .help("Rewrite the links under the gemlog heading of the index file next to each feed"),
+        Arg::with_name("id-scheme")
+            .long("id-scheme")
+            .value_name("SCHEME")
+            .help("Ids of new entries, their 'url' or a 'tag' URI (default url)")
+            .possible_values(&["url", "tag"])
+            .takes_value(true),
+        Arg::with_name("incremental")
+            .long("incremental")
+            .help("Only read again the articles changed since the previous run"),
+        Arg::with_name("max-length")
+            .long("max-length")
+            .value_name("CHARS")
+            .help("Truncate entry summaries and contents to CHARS characters")
+            .takes_value(true),
+        Arg::with_name("n")
+            .short("n")
+            .value_name("N")
+            .help("Include N most recently created files in feed (default 10)")
+            .takes_value(true),
+        Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .value_name("FILE")
+            .help("Output file name (default atom.xml, rss.xml or feed.json)")
+            .takes_value(true),
+        Arg::with_name("per-category")
+            .long("per-category")
+            .help("Also generate one feed per category, in the category directory"),
+        Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .help("Do not write on stdout under non-error conditions"),
+        Arg::with_name("sort-by")
+            .long("sort-by")
+            .value_name("DATE")
+            .help("Order entries by 'published' or 'updated' date (default published)")
+            .possible_values(&["published", "updated"])
+            .takes_value(true),
+        Arg::with_name("strict")
+            .long("strict")
+            .help("Abort on the first unusable article instead of skipping it"),
+        Arg::with_name("subtitle")
+            .short("s")
+            .long("subtitle")
+            .value_name("STR")
+            .help("Feed subtitle")
+            .takes_value(true),
+        Arg::with_name("summary")
+            .long("summary")
+            .value_name("N")
+            .help("Use the N first paragraphs after the first heading as entry summary")
+            .takes_value(true),
+        Arg::with_name("tag-authority")
+            .long("tag-authority")
+            .value_name("AUTH")
+            .help("Authority minting tag ids, e.g. 'example.org,2021' (default the host on the article date)")
+            .takes_value(true),
+        Arg::with_name("time-source")
+            .long("time-source")
+            .value_name("SOURCE")
+            .help("Date of undated articles: file 'ctime', 'mtime', or 'git' commit dates (default ctime)")
+            .possible_values(&["ctime", "mtime", "git"])
+            .conflicts_with("mtime")
+            .takes_value(true),
+        Arg::with_name("timezone")
+            .long("timezone")
+            .value_name("OFFSET")
+            .help("Timezone of dates without offset, e.g. '+01:00' (default UTC)")
+            .validator(is_timezone)
+            .takes_value(true),
+        Arg::with_name("title")
+            .short("t")
+            .long("title")
+            .value_name("STR")
+            .help("Feed title")
+            .takes_value(true),
+        Arg::with_name("write-categories")
+            .long("write-categories")
+            .value_name("FILE")
+            .help("Detect the categories as with --auto-categories, and write the undeclared ones to FILE as a configuration snippet")
+            .takes_value(true),
+        Arg::with_name("mtime")
+            .long("mtime")
+            .help("Use file modification time, not file change time, as date of undated articles"),
+    ]
 }
 
-/// Set the id, title, updated and link attributes of the provided
-/// FeedGenerator entry object according the contents of the named
-/// Gemini file and the base URL.
-fn populate_entry_from_file(
-    filepath: &str,
-    base_url: &Url,
-    time_func: fn(&str) -> time::SystemTime,
-    root: &str,
-    cat: Category,
-    clean: bool,
-) -> Entry {
-    let pfile = Path::new(filepath);
-    let proot = Path::new(root);
-    let url = if pfile.parent().unwrap() == proot {
-        base_url
-            .join(pfile.file_name().unwrap().to_str().unwrap())
-            .unwrap()
-    } else {
-        base_url.join(&filepath[root.len()..]).unwrap()
+fn main() {
+    let all = App::new("gematom")
+        .version(VERSION)
+        .author("Eric Würbel <eric@vents-sauvages.fr>")
+        .about("Generate an atom feed our of a gemini site")
+        .args(
+            &site_args()
+                .into_iter()
+                .map(|a| a.global(true))
+                .collect::<Vec<_>>(),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Regenerate the feeds whenever the categories change")
+                .arg(
+                    Arg::with_name("debounce")
+                        .long("debounce")
+                        .value_name("MS")
+                        .help("Wait until no change happened for MS milliseconds (default 500)")
+                        .takes_value(true),
+                ),
+        )
+        .get_matches();
+    // site arguments are global, and also found in the subcommand
+    let (matches, watch) = match all.subcommand_matches("watch") {
+        Some(sub) => (sub, true),
+        None => (&all, false),
     };
-    let mut entry = Entry::default();
-    entry.set_id(url.as_str());
-    let mut link = Link::default();
-    link.set_href(url.as_str());
-    link.set_rel("alternate");
-    entry.set_links(vec![link]);
-    entry.set_updated(get_update_time(filepath, time_func, cat));
-    let default_title = remove_rfc3339_date(match cat {
-        Category::FLAT => pfile.file_stem().unwrap().to_str().unwrap(),
-        Category::TREE => pfile
-            .parent()
-            .unwrap()
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap(),
-    });
-    let default_title = if clean {
-        default_title.replace("_", " ")
-    } else {
-        default_title.to_string()
+    let cli = match config_from_matches(matches) {
+        Ok(c) => c,
+        Err(e) => usage_error(&e),
     };
-    let title = extract_first_heading(filepath, &default_title);
-    entry.set_title(title);
-    entry
-}
-
-fn build_feed(
-    directory: &str,
-    categories: &HashMap<String, Category>,
-    time_func: fn(&str) -> time::SystemTime,
-    base_url: Url,
-    output: &str,
-    n: usize,
-    title: Option<&str>,
-    subtitle: Option<&str>,
-    author: Option<&str>,
-    email: Option<&str>,
-    verbose: bool,
-    clean: bool,
-) {
-    let title = match title {
-        Some(t) => String::from(t),
-        None => get_feed_title(directory, clean),
+    let config_file = match matches.value_of("config") {
+        Some(f) => Some(PathBuf::from(f)),
+        None => cli.directory.as_ref().and_then(Config::discover),
+    };
+    let mut config = match &config_file {
+        Some(f) => match Config::load(f) {
+            Ok(c) => c.merge(cli),
+            Err(e) => fail(e),
+        },
+        None => cli,
+    };
+    let directory = match (&config.directory, &config_file) {
+        (Some(d), _) => d.clone(),
+        (None, Some(f)) => f.parent().unwrap_or_else(|| Path::new(".")).to_path_buf(),
+        (None, None) => usage_error("no site directory given (use --directory)"),
     };
-    let feed_url = base_url.join(output).unwrap();
+    let base: Url = match &config.base {
+        Some(b) => b.clone(),
+        None => usage_error("no base URL given (use --base or a configuration file)"),
+    };
+    let formats = config.formats();
+    if formats.len() > 1 && config.output.is_some() {
+        usage_error("an output file name cannot be given with several formats");
+    }
+    let dry_run = matches.is_present("dry-run");
+    if dry_run && watch {
+        usage_error("--dry-run cannot be used with watch");
+    }
+    if dry_run && !formats.contains(&Format::Atom) {
+        usage_error("--dry-run only compares atom feeds");
+    }
+    let verbose = !matches.is_present("quiet");
     if verbose {
-        println!(
-            "Generating feed \"{}\", which should be served from {}",
-            title, feed_url
+        if let Some(f) = &config_file {
+            println!("Using configuration file {}", f.display());
+        }
+    }
+    let write_categories = matches.value_of("write-categories");
+    if config.auto_categories.unwrap_or(false) || write_categories.is_some() {
+        let detected = match detect_categories(&directory) {
+            Ok(d) => d,
+            Err(e) => fail(e),
+        };
+        let mut added = Vec::new();
+        // the detected layout is the point of these options, and is
+        // printed even with --quiet
+        for (dir, typ, articles) in detected {
+            let declared = config.categories.iter().any(|c| c.dir == dir);
+            println!(
+                "Detected {} category {} with {} articles{}",
+                typ,
+                dir,
+                articles,
+                if declared { ", already declared" } else { "" }
+            );
+            if !declared {
+                added.push(CategoryConfig::new(dir, typ));
+            }
+        }
+        // like the feeds, the snippet is not written by a dry run
+        match write_categories {
+            Some(f) if !dry_run => {
+                if let Err(e) = gematom::write_categories(f, &added) {
+                    fail(e);
+                }
+                if verbose {
+                    println!("wrote {} categories to {:?}", added.len(), f);
+                }
+            }
+            Some(f) if verbose => println!("would write {} categories to {:?}", added.len(), f),
+            _ => {}
+        }
+        config.categories.extend(added);
+    }
+    if config.categories.is_empty() {
+        usage_error(
+            "no category given (use --category, --auto-categories or a configuration file)",
         );
     }
-    let mut feed = Feed::default();
-    feed.set_id(base_url.as_str());
-    feed.set_title(title);
-    feed.set_subtitle(if let Some(s) = subtitle {
-        Some(String::from(s))
-    } else {
-        None
-    });
-    let mut gen = Generator::default();
-    gen.set_value("gematom, an atom feed generator for gemini.");
-    gen.set_uri("https://github.com/drtutut/gematom".to_string());
-    gen.set_version(VERSION.to_string());
-    feed.set_generator(gen);
-    feed.set_rights("© Éric Würbel 2021".to_string());
-    let mut person = Person::default();
-    if let Some(a) = author {
-        person.set_name(a);
+    let builder = FeedBuilder::new(&directory, base)
+        .verbose(verbose && !dry_run)
+        .dry_run(dry_run);
+    let builder = config.apply(builder);
+    if dry_run {
+        match compare(&builder, &config, &directory, verbose) {
+            Ok(false) => return,
+            Ok(true) => process::exit(DIFFERENCES),
+            Err(e) => fail(e),
+        }
     }
-    person.set_email(if let Some(e) = email {
-        Some(String::from(e))
-    } else {
-        None
-    });
-    if person.name != "" || person.email != None {
-        let v = vec![person];
-        feed.set_authors(v);
+    if !watch {
+        if let Err(e) = generate(&builder, &config, &directory, &formats, verbose) {
+            fail(e);
+        }
+        return;
     }
-    let mut self_link = Link::default();
-    let mut alt_link = Link::default();
-    self_link.set_href(base_url.as_str());
-    self_link.set_rel("self");
-    alt_link.set_href(base_url.as_str());
-    alt_link.set_rel("alternate");
-    let v = vec![self_link, alt_link];
-    feed.set_links(v);
 
-    // TODO: this shoud return a vector of tuples (file, category
-    // type) because we need this to infer a default title.
-    let files = match get_files(directory, categories, time_func, n) {
-        None => {
-            if verbose {
-                println!("No world-readable gemini content found! :(");
-            }
-            return;
+    let debounce = match usize_option(matches, "debounce") {
+        Ok(ms) => Duration::from_millis(ms.unwrap_or(500) as u64),
+        Err(e) => usage_error(&e),
+    };
+    let dirs = config
+        .categories
+        .iter()
+        .map(|c| directory.join(&c.dir))
+        .collect();
+    // generated files must not trigger a new generation
+    let generated = config
+        .generated_files(&builder.feed_paths())
+        .iter()
+        .map(|p| directory.join(p))
+        .collect();
+    let mut watcher = match Watcher::new(dirs, generated) {
+        Ok(w) => w,
+        Err(e) => fail(e),
+    };
+    let mut last = match generate(&builder, &config, &directory, &formats, verbose) {
+        Ok(feed) => feed,
+        Err(e) => {
+            eprintln!("gematom: {}", e);
+            Feed::default()
         }
-        Some(f) => f,
     };
-    let mut entries = Vec::new();
-    for fp in files {
-        let Pair(f, cat) = fp;
-        let entry = populate_entry_from_file(&f, &base_url, time_func, directory, cat, clean);
+    loop {
+        let changed = match watcher.wait(debounce) {
+            Ok(c) => c,
+            Err(e) => fail(e),
+        };
         if verbose {
-            println!("Adding {} with title {}", &f, entry.title());
+            for path in &changed {
+                println!("changed {}", path.display());
+            }
+        }
+        match generate(&builder, &config, &directory, &formats, verbose) {
+            Ok(feed) => {
+                if verbose {
+                    for change in diff::changes(&last, &feed) {
+                        println!("{}", change);
+                    }
+                }
+                last = feed;
+            }
+            Err(e) => eprintln!("gematom: {}", e),
         }
-        entries.push(entry)
     }
-    if entries.len() != 0 {
-        feed.set_updated(*entries[0].updated());
-        feed.set_entries(entries);
+}
+
+/// Build the feeds of the site and compare them with the written
+/// atom feeds, printing the changes unless `verbose` is unset.
+/// Returns whether there are changes.
+fn compare(
+    builder: &FeedBuilder,
+    config: &Config,
+    directory: &Path,
+    verbose: bool,
+) -> Result<bool, GemAtomError> {
+    let (feeds, skipped) = builder.build_all()?;
+    if !skipped.is_empty() {
+        eprintln!("gematom: would skip {} article(s):", skipped.len());
+        for d in &skipped {
+            eprintln!("  {}", d);
+        }
+    }
+    let mut changed = false;
+    for generated in &feeds {
+        let outpath = directory.join(config.output_path(Format::Atom, &generated.path));
+        let changes = diff::changes(&read_feed(&outpath)?, &generated.feed);
+        if changes.is_empty() {
+            continue;
+        }
+        changed = true;
+        if verbose {
+            println!("{}:", outpath.display());
+            for change in &changes {
+                println!("  {}", change);
+            }
+        }
     }
-    // write the file.
-    let mut outpath = PathBuf::new();
-    outpath.push(directory);
-    outpath.push(output);
-    println!("outputting to {:?}", outpath);
-    let out = fs::File::create(outpath).unwrap();
-    feed.write_to(out).unwrap();
+    Ok(changed)
 }
 
-fn main() {
-    let matches = App::new("gematom")
-        .version(VERSION)
-        .author("Eric Würbel <eric@vents-sauvages.fr>")
-        .about("Generate an atom feed our of a gemini site")
-        .arg(
-            Arg::with_name("author")
-                .short("a")
-                .long("author")
-                .value_name("NAME")
-                .help("Author name")
-                .default_value("")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("base")
-                .short("b")
-                .long("base")
-                .value_name("URL")
-                .help("Base URL for feed and entries")
-                .validator(is_gemini_url)
-                .required(true)
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("category")
-                .short("c")
-                .long("category")
-                .value_name("DIR:TYPE")
-                .help("Category of a subdir. 'flat' ou 'tree'")
-                .multiple(true)
-                .required(true)
-                .validator(is_category)
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("clean-title")
-                .short("C")
-                .long("clean-title")
-                .help("When using a file or directory name as a title, convert '_' into space."),
-        )
-        .arg(
-            Arg::with_name("directory")
-                .short("d")
-                .long("directory")
-                .value_name("DIR")
-                .help("Root directory of the site")
-                .required(true)
-                .validator(is_valid_directory)
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("email")
-                .short("e")
-                .long("email")
-                .value_name("EMAIL")
-                .help("author's email address")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("n")
-                .short("n")
-                .value_name("N")
-                .help("Include N most recently created files in feed (default 10)")
-                .default_value("10")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("output")
-                .short("o")
-                .long("output")
-                .value_name("FILE")
-                .default_value("atom.xml")
-                .help("Output file name")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("quiet")
-                .short("q")
-                .long("quiet")
-                .help("Do not write on stdout under non-error conditions"),
-        )
-        .arg(
-            Arg::with_name("subtitle")
-                .short("s")
-                .long("subtitle")
-                .value_name("STR")
-                .help("Feed subtitle")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("title")
-                .short("t")
-                .long("title")
-                .value_name("STR")
-                .help("Feed title")
-                .takes_value(true),
-        )
-        .arg(
-            Arg::with_name("mtime")
-                .long("mtime")
-                .help("Use file modification time, not file change time"),
-        )
-        .get_matches();
-    let base = Url::parse(matches.value_of("base").unwrap()).unwrap();
-    let categories = match parse_categories(&mut matches.values_of("category").unwrap()) {
-        Ok(cts) => cts,
-        Err(e) => {
-            println!("{}", e);
-            return;
+/// Build the feeds of the site and write them in every format, along
+/// with the gemlog pages. Returns the site-wide feed.
+fn generate(
+    builder: &FeedBuilder,
+    config: &Config,
+    directory: &Path,
+    formats: &[Format],
+    verbose: bool,
+) -> Result<Feed, GemAtomError> {
+    let (feeds, skipped) = builder.build_all()?;
+    if !skipped.is_empty() {
+        eprintln!("gematom: skipped {} article(s):", skipped.len());
+        for d in &skipped {
+            eprintln!("  {}", d);
         }
-    };
-    let directory = matches.value_of("directory").unwrap();
-    let n = value_t!(matches, "n", usize).unwrap_or(10);
-    let output = matches.value_of("output").unwrap();
-    let verbose = !matches.is_present("quiet");
-    let clean_title = matches.is_present("clean-title");
-    let time_func = if matches.is_present("mtime") {
-        mtime
-    } else {
-        ctime
-    };
-    build_feed(
-        directory,
-        &categories,
-        time_func,
-        base,
-        output,
-        n,
-        matches.value_of("title"),
-        matches.value_of("subtitle"),
-        matches.value_of("author"),
-        matches.value_of("email"),
-        verbose,
-        clean_title,
-    );
+    }
+    // write the files.
+    for generated in &feeds {
+        if generated.feed.entries().is_empty() {
+            continue;
+        }
+        for format in formats {
+            let relpath = config.output_path(*format, &generated.path);
+            let outpath = directory.join(&relpath);
+            if verbose {
+                println!("outputting to {:?}", outpath);
+            }
+            // the file is served next to the feed
+            let url = relpath
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| generated.url.join(n).ok());
+            let backup = config.backup.unwrap_or(false);
+            if !format.write(&generated.feed, &outpath, url.as_ref(), backup)? && verbose {
+                println!("{:?} is unchanged", outpath);
+            }
+        }
+        let feed_path = directory.join(&generated.path);
+        let feed_dir = match feed_path.parent() {
+            Some(d) => d,
+            None => directory,
+        };
+        if let Some(gemlog_path) = config.gemlog_path(&generated.path) {
+            let gemlog_path = directory.join(gemlog_path);
+            if verbose {
+                println!("outputting to {:?}", gemlog_path);
+            }
+            gemlog::write_gemlog(&generated.feed, &gemlog_path)?;
+        }
+        if config.gemlog_index.unwrap_or(false) {
+            let name = generated.category.as_deref().unwrap_or(ROOT_CATEGORY);
+            let files = config
+                .categories
+                .iter()
+                .find(|c| c.dir == name)
+                .and_then(CategoryConfig::files)
+                .unwrap_or_default();
+            let heading = match &config.gemlog_heading {
+                Some(h) => h.as_str(),
+                None => gemlog::SECTION_HEADING,
+            };
+            if let Some(index) = gemlog::update_index(&generated.feed, feed_dir, &files, heading)? {
+                if verbose {
+                    println!("updated {:?}", index);
+                }
+            }
+        }
+    }
+    Ok(feeds.into_iter().next().map(|g| g.feed).unwrap_or_default())
 }
 
 #[cfg(test)]
@@ -600,6 +645,7 @@ mod tests {
     fn test_is_category() {
         assert_eq!(is_category(String::from("blabla:flat")), Ok(()));
         assert_eq!(is_category(String::from("news:tree")), Ok(()));
+        assert_eq!(is_category(String::from("log:dated")), Ok(()));
         assert_eq!(
             is_category(String::from("vers:zorgl")),
             Err(String::from("Not a valid category: zorgl"))
@@ -654,17 +700,4 @@ mod tests {
             Err(String::from("Invalid directory: /dev/core"))
         );
     }
-
-    #[test]
-    fn test_is_file() {
-        assert!(is_file("/etc/hosts"));
-        assert!(!is_file("/etc"));
-        assert!(!is_file("/dev/loop0"));
-    }
-
-    #[test]
-    fn test_is_world_readable() {
-        assert!(is_world_readable("/etc/hosts"));
-        assert!(!is_world_readable("/etc/shadow"));
-    }
 }
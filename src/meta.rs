@@ -0,0 +1,168 @@
+use crate::gemtext::{Document, Line};
+
+/// Alt text of a preformatted block holding the metadata of an
+/// article.
+const FENCE_ALT: &str = "gematom";
+
+/// Keys recognised in a metadata block.
+const KEYS: [&str; 8] = [
+    "id",
+    "title",
+    "published",
+    "updated",
+    "author",
+    "tags",
+    "summary",
+    "draft",
+];
+
+/// Metadata given at the top of an article. They take precedence
+/// over the title and dates guessed from the article.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Metadata {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub published: Option<String>,
+    pub updated: Option<String>,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+    pub summary: Option<String>,
+    pub draft: bool,
+}
+
+impl Metadata {
+    /// Split a gemtext document into its metadata block and the
+    /// remaining document. The block is either a run of `key: value`
+    /// lines ended by a blank line, with known keys only, or a closed
+    /// preformatted block whose alt text is `gematom`, both at the
+    /// very top of the document. Documents without such a block have
+    /// empty metadata. Dates are kept as written, see
+    /// [`crate::date::parse_date`].
+    pub fn split<'a>(doc: &Document<'a>) -> (Metadata, Document<'a>) {
+        match block(doc) {
+            Some((fields, n)) => {
+                let mut meta = Metadata::default();
+                for (key, value) in fields {
+                    meta.set(key, value);
+                }
+                // nor are the empty lines following the block
+                let empty = doc.lines()[n..]
+                    .iter()
+                    .take_while(|l| **l == Line::Text(""))
+                    .count();
+                (meta, doc.rest(n + empty))
+            }
+            None => (Metadata::default(), doc.clone()),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "id" => self.id = Some(value.to_string()),
+            "title" => self.title = Some(value.to_string()),
+            "published" => self.published = Some(value.to_string()),
+            "updated" => self.updated = Some(value.to_string()),
+            "author" => self.author = Some(value.to_string()),
+            "tags" => {
+                self.tags = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|t| !t.is_empty())
+                    .map(String::from)
+                    .collect()
+            }
+            "summary" => self.summary = Some(value.to_string()),
+            "draft" => self.draft = ["true", "yes"].contains(&value),
+            // unknown keys are allowed in a fenced block
+            _ => {}
+        }
+    }
+}
+
+/// Split a `key: value` line.
+fn field(line: &str) -> Option<(&str, &str)> {
+    let mut parts = line.splitn(2, ':');
+    let key = parts.next()?.trim();
+    let value = parts.next()?.trim();
+    Some((key, value))
+}
+
+/// Find the metadata block at the top of `doc`, returning its fields
+/// and the number of lines it spans.
+fn block<'a>(doc: &Document<'a>) -> Option<(Vec<(&'a str, &'a str)>, usize)> {
+    match doc.lines().first()? {
+        Line::Preformatted {
+            alt: Some(alt),
+            lines,
+        } if *alt == FENCE_ALT => {
+            // an unterminated block extends to the end of the text
+            let closing = doc.source(0).lines().skip(1).last();
+            if !closing.is_some_and(|l| l.starts_with("```")) {
+                return None;
+            }
+            Some((lines.iter().filter_map(|l| field(l)).collect(), 1))
+        }
+        _ => {
+            let mut fields = Vec::new();
+            let mut n = 0;
+            for line in doc.lines() {
+                n += 1;
+                match line {
+                    // a blank line ends the block
+                    Line::Text(t) if t.trim().is_empty() => break,
+                    Line::Text(t) => match field(t) {
+                        Some(f) if KEYS.contains(&f.0) => fields.push(f),
+                        _ => return None,
+                    },
+                    _ => return None,
+                }
+            }
+            if fields.is_empty() {
+                None
+            } else {
+                Some((fields, n))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let split = |text| {
+            let (meta, rest) = Metadata::split(&Document::parse(text));
+            (meta, rest.text())
+        };
+        let (meta, rest) = split(
+            "title: Spam\npublished: 2021-03-04\ntags: a, b\ndraft: yes\nid: tag:a,2021:b\n\n# Eggs\n",
+        );
+        assert_eq!(meta.id.as_deref(), Some("tag:a,2021:b"));
+        assert_eq!(meta.title.as_deref(), Some("Spam"));
+        assert_eq!(meta.published.as_deref(), Some("2021-03-04"));
+        assert_eq!(meta.tags, vec!["a", "b"]);
+        assert!(meta.draft);
+        assert_eq!(rest, "# Eggs\n");
+
+        let (meta, rest) =
+            split("```gematom\nupdated: 2021-03-04T14:30:00+01:00\nfoo: bar\n```\n\ntext\n");
+        assert_eq!(meta.updated.as_deref(), Some("2021-03-04T14:30:00+01:00"));
+        assert_eq!(rest, "text\n");
+
+        // not metadata
+        let text = "Note: this is text\n\n# Spam\n";
+        assert_eq!(split(text), (Metadata::default(), text));
+        let text = "```gematom\ntitle: spam\n";
+        assert_eq!(split(text), (Metadata::default(), text));
+        let text = "* title: spam\n\n# Eggs\n";
+        assert_eq!(split(text), (Metadata::default(), text));
+
+        // a block up to the end of the text, with CRLF line endings
+        let (meta, rest) = split("title: Spam\r\ndraft: no\r\n");
+        assert_eq!(meta.title.as_deref(), Some("Spam"));
+        assert!(!meta.draft);
+        assert_eq!(rest, "");
+    }
+}
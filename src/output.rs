@@ -0,0 +1,168 @@
+use std::fs;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+use atom_syndication::{Feed, Person};
+use serde::Deserialize;
+use url::Url;
+
+use crate::error::{GemAtomError, Result};
+use crate::json_feed::to_json_feed;
+use crate::rss_feed::to_channel;
+use crate::util::{write_if_changed, xml_escape, Access};
+
+/// Syndication format of a written feed.
+#[derive(PartialEq, Eq, Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Atom 1.0.
+    Atom,
+    /// RSS 2.0.
+    Rss,
+    /// JSON Feed 1.1.
+    JsonFeed,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// Build a format from a string.
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "atom" => Ok(Format::Atom),
+            "rss" => Ok(Format::Rss),
+            "jsonfeed" => Ok(Format::JsonFeed),
+            _ => Err(format!("Invalid format {}", s)),
+        }
+    }
+}
+
+impl Format {
+    /// Default name of the files written in this format.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Format::Atom => "atom.xml",
+            Format::Rss => "rss.xml",
+            Format::JsonFeed => "feed.json",
+        }
+    }
+
+    /// Write `feed`, converted into this format, to the file at
+    /// `path`, served from `url` if known. The file is left untouched
+    /// if it already holds the same feed, and otherwise replaced
+    /// atomically, keeping the previous version with a `.bak` suffix
+    /// if `backup` is set. Returns whether the file was written.
+    pub fn write(self, feed: &Feed, path: &Path, url: Option<&Url>, backup: bool) -> Result<bool> {
+        let rendered = match self {
+            Format::Atom => escape_feed(feed).write_to(Vec::new()).map_err(Box::from),
+            Format::Rss => to_channel(feed).write_to(Vec::new()).map_err(Box::from),
+            Format::JsonFeed => {
+                serde_json::to_vec_pretty(&to_json_feed(feed, url)).map_err(Box::from)
+            }
+        };
+        let rendered = rendered.map_err(|e| GemAtomError::FeedWrite {
+            path: path.to_path_buf(),
+            source: e,
+        })?;
+        write_if_changed(path, &rendered, Access::Served, backup)
+    }
+}
+
+/// Write `feed` to the file at `path`, in the atom format.
+pub fn write_feed(feed: &Feed, path: &Path) -> Result<()> {
+    Format::Atom.write(feed, path, None, false).map(|_| ())
+}
+
+/// Read the atom feed written at `path`. A missing file is read as
+/// an empty feed.
+pub fn read_feed(path: &Path) -> Result<Feed> {
+    let file = match fs::File::open(path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Feed::default()),
+        Err(e) => return Err(GemAtomError::io(path, e)),
+    };
+    Feed::read_from(BufReader::new(file))
+        .map_err(|e| GemAtomError::io(path, io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+/// Returns a copy of `feed` whose texts and ids are XML-escaped, as
+/// atom_syndication writes them verbatim. Attributes, such as link
+/// hrefs, are escaped by atom_syndication itself.
+fn escape_feed(feed: &Feed) -> Feed {
+    let escape_person = |p: &Person| {
+        let mut p = p.clone();
+        p.set_name(xml_escape(p.name()));
+        p.set_email(p.email().map(xml_escape));
+        p.set_uri(p.uri().map(xml_escape));
+        p
+    };
+    let mut feed = feed.clone();
+    feed.set_id(xml_escape(feed.id()));
+    feed.set_title(xml_escape(feed.title()));
+    feed.set_subtitle(feed.subtitle().map(xml_escape));
+    feed.set_rights(feed.rights().map(xml_escape));
+    feed.set_authors(feed.authors().iter().map(escape_person).collect::<Vec<_>>());
+    let entries: Vec<_> = feed
+        .entries()
+        .iter()
+        .map(|e| {
+            let mut e = e.clone();
+            e.set_id(xml_escape(e.id()));
+            e.set_title(xml_escape(e.title()));
+            e.set_summary(e.summary().map(xml_escape));
+            e.set_authors(e.authors().iter().map(escape_person).collect::<Vec<_>>());
+            if let Some(content) = e.content() {
+                let mut content = content.clone();
+                if content.content_type() != Some("xhtml") {
+                    content.set_value(content.value().map(xml_escape));
+                }
+                e.set_content(content);
+            }
+            e
+        })
+        .collect();
+    feed.set_entries(entries);
+    feed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+    use atom_syndication::{Category, Entry, Link};
+
+    #[test]
+    fn test_read_feed() {
+        let dir = TempDir::new("read");
+        let path = dir.join("atom.xml");
+        let mut entry = Entry::default();
+        entry.set_id("gemini://example.org/fish&chips.gmi");
+        entry.set_title("A & B");
+        let mut link = Link::default();
+        link.set_href("gemini://example.org/fish&chips.gmi");
+        entry.set_links(vec![link]);
+        let mut category = Category::default();
+        category.set_term("spam & eggs");
+        entry.set_categories(vec![category]);
+        let mut feed = Feed::default();
+        feed.set_id("gemini://example.org/?a&b");
+        feed.set_title("Site");
+        feed.set_entries(vec![entry]);
+        write_feed(&feed, &path).unwrap();
+        let read = read_feed(&path).unwrap();
+        assert_eq!(read.id(), "gemini://example.org/?a&b");
+        let entry = &read.entries()[0];
+        assert_eq!(entry.title(), "A & B");
+        assert_eq!(entry.id(), "gemini://example.org/fish&chips.gmi");
+        assert_eq!(
+            entry.links()[0].href(),
+            "gemini://example.org/fish&chips.gmi"
+        );
+        assert_eq!(entry.categories()[0].term(), "spam & eggs");
+        assert!(read_feed(&dir.join("missing.xml"))
+            .unwrap()
+            .entries()
+            .is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+use atom_syndication::{Entry, Feed, Link, Person};
+use rss::{Category, Channel, Guid, Item};
+
+/// Returns the href of the first link of `links` with relation `rel`.
+pub(crate) fn link_href<'a>(links: &'a [Link], rel: &str) -> Option<&'a str> {
+    links.iter().find(|l| l.rel() == rel).map(|l| l.href())
+}
+
+/// RSS only knows authors as email addresses, optionally followed by
+/// a name between parentheses.
+fn rss_author(person: &Person) -> Option<String> {
+    person
+        .email()
+        .map(|email| format!("{} ({})", email, person.name()))
+}
+
+/// Convert an atom entry into an RSS item. Gemini content has no RSS
+/// counterpart and is left out, HTML and XHTML content is kept as
+/// `content:encoded`.
+fn to_item(entry: &Entry) -> Item {
+    let mut item = Item::default();
+    item.set_title(entry.title().to_string());
+    let link = link_href(entry.links(), "alternate");
+    item.set_link(link.map(String::from));
+    let mut guid = Guid::default();
+    guid.set_value(entry.id());
+    guid.set_permalink(link == Some(entry.id()));
+    item.set_guid(guid);
+    let date = entry.published().unwrap_or_else(|| entry.updated());
+    item.set_pub_date(date.to_rfc2822());
+    item.set_description(entry.summary().map(|s| s.to_string()));
+    if let Some(content) = entry.content() {
+        if matches!(content.content_type(), Some("html") | Some("xhtml")) {
+            item.set_content(content.value().map(String::from));
+        }
+    }
+    item.set_author(entry.authors().first().and_then(rss_author));
+    let categories: Vec<_> = entry
+        .categories()
+        .iter()
+        .map(|c| {
+            let mut cat = Category::default();
+            cat.set_name(c.term());
+            cat
+        })
+        .collect();
+    item.set_categories(categories);
+    item
+}
+
+/// Convert an atom feed into an RSS 2.0 channel.
+pub(crate) fn to_channel(feed: &Feed) -> Channel {
+    let mut channel = Channel::default();
+    channel.set_title(feed.title().to_string());
+    let link = link_href(feed.links(), "alternate").unwrap_or_else(|| feed.id());
+    channel.set_link(link);
+    channel.set_description(feed.subtitle().unwrap_or_else(|| feed.title()).to_string());
+    channel.set_copyright(feed.rights().map(|r| r.to_string()));
+    channel.set_generator(feed.generator().map(|g| g.value().to_string()));
+    channel.set_last_build_date(feed.updated().to_rfc2822());
+    channel.set_managing_editor(feed.authors().first().and_then(rss_author));
+    channel.set_items(feed.entries().iter().map(to_item).collect::<Vec<_>>());
+    channel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atom_syndication::{Content, FixedDateTime};
+
+    #[test]
+    fn test_to_channel() {
+        let mut entry = Entry::default();
+        entry.set_id("gemini://example.org/a.gmi");
+        entry.set_title("A & B");
+        let mut link = Link::default();
+        link.set_href("gemini://example.org/a.gmi");
+        entry.set_links(vec![link]);
+        entry.set_updated(FixedDateTime::parse_from_rfc3339("2021-03-04T00:00:00Z").unwrap());
+        let mut content = Content::default();
+        content.set_value("<p>a</p>".to_string());
+        content.set_content_type("html".to_string());
+        entry.set_content(content);
+        let mut feed = Feed::default();
+        feed.set_title("Site");
+        feed.set_id("gemini://example.org/");
+        let mut person = Person::default();
+        person.set_name("Eric");
+        person.set_email(Some("eric@example.org".to_string()));
+        feed.set_authors(vec![person]);
+        feed.set_entries(vec![entry]);
+
+        let channel = to_channel(&feed);
+        assert_eq!(channel.link(), "gemini://example.org/");
+        assert_eq!(channel.description(), "Site");
+        assert_eq!(channel.managing_editor(), Some("eric@example.org (Eric)"));
+        let item = &channel.items()[0];
+        assert_eq!(item.title(), Some("A & B"));
+        assert_eq!(
+            item.pub_date()
+                .map(|d| FixedDateTime::parse_from_rfc2822(d).unwrap()),
+            Some(FixedDateTime::parse_from_rfc3339("2021-03-04T00:00:00Z").unwrap())
+        );
+        assert!(item.guid().unwrap().is_permalink());
+        assert_eq!(item.content(), Some("<p>a</p>"));
+
+        let xml = channel.to_string();
+        assert!(xml.contains("<title>A &amp; B</title>"));
+        assert!(xml.contains("xmlns:content="));
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use atom_syndication::FixedDateTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::{GemAtomError, Result};
+use crate::util::{write_if_changed, Access};
+
+/// Name of the state file kept in the root directory of the site.
+pub const STATE_FILE: &str = ".gematom-state.json";
+
+/// What is known of an article from a previous run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Record {
+    /// Id of the entry, kept once given.
+    pub id: String,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+    /// SHA-256 of the article file, in hexadecimal.
+    pub hash: String,
+    pub title: String,
+    pub published: Option<FixedDateTime>,
+    pub updated: FixedDateTime,
+    /// What the update date was taken from.
+    pub origin: String,
+    pub draft: bool,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+    pub summary: Option<String>,
+}
+
+impl Record {
+    /// Whether the file described by `meta` has, as far as its size
+    /// and modification time tell, not changed since this record.
+    pub fn is_fresh(&self, meta: &fs::Metadata) -> bool {
+        self.size == meta.len()
+            && self.mtime == meta.mtime()
+            && self.mtime_nsec == meta.mtime_nsec()
+    }
+
+    /// Update the size and modification time of the record to those
+    /// of `meta`.
+    pub fn touch(&mut self, meta: &fs::Metadata) {
+        self.size = meta.len();
+        self.mtime = meta.mtime();
+        self.mtime_nsec = meta.mtime_nsec();
+    }
+}
+
+/// Hash of the contents of an article.
+pub(crate) fn hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// Records of the articles of a site, indexed by path relative to the
+/// site root, along with the options they were computed with.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct State {
+    options: String,
+    articles: BTreeMap<String, Record>,
+    /// Ids of the articles, indexed by path relative to the site
+    /// root, which outlive the records when the options change.
+    #[serde(default)]
+    ids: BTreeMap<String, String>,
+}
+
+impl State {
+    /// An empty state for the given `options`.
+    pub fn new(options: String) -> State {
+        State {
+            options,
+            articles: BTreeMap::new(),
+            ids: BTreeMap::new(),
+        }
+    }
+
+    /// Load the state saved at `path`. A missing or unreadable state
+    /// is taken as empty, and only the ids of one saved with other
+    /// options are kept.
+    pub fn load(path: &Path, options: String) -> State {
+        let state = fs::read(path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<State>(&bytes).ok());
+        match state {
+            Some(state) if state.options == options => state,
+            Some(state) => State {
+                ids: state.ids,
+                ..State::new(options)
+            },
+            None => State::new(options),
+        }
+    }
+
+    /// Save the state to `path`, unless it is unchanged.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let bytes =
+            serde_json::to_vec_pretty(self).map_err(|e| GemAtomError::io(path, e.into()))?;
+        write_if_changed(path, &bytes, Access::Private, false).map(|_| ())
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Record> {
+        self.articles.get(path)
+    }
+
+    /// Id of the article at `path`, even if its record was dropped.
+    pub fn id(&self, path: &str) -> Option<&str> {
+        self.ids.get(path).map(String::as_str)
+    }
+
+    /// A record of an article which no longer exists under `root`,
+    /// with the given `hash`: the article was probably renamed.
+    pub fn renamed(&self, root: &Path, hash: &str) -> Option<&Record> {
+        self.articles
+            .iter()
+            .find(|(path, r)| r.hash == hash && !root.join(path).exists())
+            .map(|(_, r)| r)
+    }
+
+    /// Keep the ids of `old` which this state has not recorded, as
+    /// long as their article still exists under `root`, e.g. as a
+    /// draft. The ids of the articles which are gone are dropped, a
+    /// renamed article having been recorded under its new path.
+    pub fn keep_ids(&mut self, old: &State, root: &Path) {
+        for (path, id) in &old.ids {
+            if !self.ids.contains_key(path) && root.join(path).exists() {
+                self.ids.insert(path.clone(), id.clone());
+            }
+        }
+    }
+
+    pub fn insert(&mut self, path: String, record: Record) {
+        self.ids.insert(path.clone(), record.id.clone());
+        self.articles.insert(path, record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn test_load_save() {
+        let dir = TempDir::new("state");
+        let path = dir.join(STATE_FILE);
+        let mut state = State::new(String::from("spam"));
+        state.insert(
+            String::from("texts/foo.gmi"),
+            Record {
+                id: String::from("gemini://example.org/texts/foo.gmi"),
+                size: 4,
+                mtime: 1_000_000,
+                mtime_nsec: 0,
+                hash: hash(b"foo\n"),
+                title: String::from("Foo"),
+                published: None,
+                updated: FixedDateTime::parse_from_rfc3339("2021-03-04T14:30:00+01:00").unwrap(),
+                origin: String::from("modification time"),
+                draft: false,
+                author: None,
+                tags: vec![String::from("eggs")],
+                summary: None,
+            },
+        );
+        state.save(&path).unwrap();
+        assert!(state.renamed(&dir, &hash(b"foo\n")).is_some());
+        assert!(state.renamed(&dir, &hash(b"bar\n")).is_none());
+        assert_eq!(State::load(&path, String::from("spam")), state);
+        // other options, or no state at all
+        let other = State::load(&path, String::from("eggs"));
+        assert!(other.get("texts/foo.gmi").is_none());
+        assert_eq!(
+            other.id("texts/foo.gmi"),
+            Some("gemini://example.org/texts/foo.gmi")
+        );
+        assert_eq!(
+            State::load(&dir.join("missing.json"), String::from("spam")),
+            State::new(String::from("spam"))
+        );
+    }
+
+    #[test]
+    fn test_keep_ids() {
+        let dir = TempDir::with_files("keep-ids", &["texts/draft.gmi"], "# Draft\n");
+        let mut old = State::new(String::from("spam"));
+        old.ids
+            .insert(String::from("texts/draft.gmi"), String::from("urn:draft"));
+        old.ids
+            .insert(String::from("texts/gone.gmi"), String::from("urn:gone"));
+        let mut state = State::new(String::from("spam"));
+        state.keep_ids(&old, &dir);
+        assert_eq!(state.id("texts/draft.gmi"), Some("urn:draft"));
+        assert_eq!(state.id("texts/gone.gmi"), None);
+        assert!(!dir.join("texts/gone.gmi").exists());
+    }
+}
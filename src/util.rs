@@ -0,0 +1,272 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use url::Url;
+
+use crate::error::{GemAtomError, Result};
+use crate::gemtext::Document;
+use crate::meta::Metadata;
+
+/// Parse a gemini URL. Only the `gemini` scheme is accepted, without
+/// user authentication.
+pub fn parse_gemini_url(val: &str) -> std::result::Result<Url, String> {
+    let url = match Url::parse(val) {
+        Ok(u) => u,
+        Err(e) => return Err(format!("{}", e)),
+    };
+    if url.scheme() != "gemini" {
+        return Err(format!("Bad url scheme : {}", url.scheme()));
+    };
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err(format!("user authentication not allowed in url {}", val));
+    };
+    Ok(url)
+}
+
+/// Returns the UTF-8 representation of a path.
+pub(crate) fn path_str(path: &Path) -> Result<&str> {
+    path.to_str()
+        .ok_or_else(|| GemAtomError::InvalidPath(path.to_path_buf()))
+}
+
+/// Returns true if val is a regular file.
+pub(crate) fn is_file(val: &str) -> bool {
+    match fs::metadata(val) {
+        Ok(meta) => meta.is_file(),
+        _ => false,
+    }
+}
+
+/// Returns true if val is a world readable pathname.
+pub(crate) fn is_world_readable(val: &str) -> bool {
+    match fs::metadata(val) {
+        Ok(meta) => (meta.permissions().mode() & 0o4) != 0,
+        _ => false,
+    }
+}
+
+/// Returns the first world readable index file of a directory named
+/// as in `names`, if any.
+pub(crate) fn find_index(dir: &Path, names: &[String]) -> Option<PathBuf> {
+    names
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|path| match path.to_str() {
+            Some(p) => is_file(p) && is_world_readable(p),
+            None => false,
+        })
+}
+
+/// Read a gemini file.
+pub(crate) fn read_gemtext(filename: &str) -> Result<String> {
+    fs::read_to_string(filename).map_err(|e| GemAtomError::io(filename, e))
+}
+
+/// Who may read a written file.
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub(crate) enum Access {
+    /// Everyone, the file being served, as feeds and gemlog pages.
+    Served,
+    /// As before for an existing file, only its owner for a new one.
+    Private,
+    /// As before for an existing file, as the umask allows for a new
+    /// one, as any file the user writes.
+    Default,
+}
+
+/// Write `contents` to the file at `path`, unless it already holds
+/// exactly these contents, see [`write_atomically`]. Returns whether
+/// the file was written.
+pub(crate) fn write_if_changed(
+    path: &Path,
+    contents: &[u8],
+    access: Access,
+    backup: bool,
+) -> Result<bool> {
+    if let Ok(existing) = fs::read(path) {
+        if existing == contents {
+            return Ok(false);
+        }
+    }
+    write_atomically(path, contents, access, backup)?;
+    Ok(true)
+}
+
+/// Write `contents` to the file at `path`, so that readers see either
+/// the old or the new file, never a truncated one: the contents are
+/// written and synced to a temporary file of the same directory,
+/// which is then renamed. Its mode is set according to `access`. If
+/// `backup` is set, the previous file is kept with a `.bak` suffix.
+pub(crate) fn write_atomically(
+    path: &Path,
+    contents: &[u8],
+    access: Access,
+    backup: bool,
+) -> Result<()> {
+    let io_error = |e| GemAtomError::io(path, e);
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let name = match path.file_name() {
+        Some(n) => n.to_string_lossy(),
+        None => return Err(GemAtomError::InvalidPath(path.to_path_buf())),
+    };
+    let previous = fs::metadata(path).ok();
+    let mode = match (&previous, access) {
+        (Some(meta), Access::Served) => Some(meta.permissions().mode() | 0o444),
+        (None, Access::Served) => Some(0o644),
+        (Some(meta), _) => Some(meta.permissions().mode()),
+        (None, Access::Private) => Some(0o600),
+        (None, Access::Default) => None,
+    };
+    let temp = dir.join(format!(".{}.tmp", name));
+    let written = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents)?;
+        if let Some(mode) = mode {
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+        }
+        file.sync_all()
+    })();
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(GemAtomError::io(&temp, e));
+    }
+    if backup && previous.is_some() {
+        let bak = dir.join(format!("{}.bak", name));
+        fs::copy(path, &bak).map_err(|e| GemAtomError::io(&bak, e))?;
+    }
+    fs::rename(&temp, path).map_err(io_error)?;
+    // make the rename itself durable
+    fs::File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| GemAtomError::io(dir, e))
+}
+
+/// Extract the title of a gemini file: the title given in its
+/// metadata, or else its first heading. If there is none, return a
+/// default string.
+///
+/// No check is made concerning the existence of the file.
+pub(crate) fn extract_title(filename: &str, default: &str) -> Result<String> {
+    let text = read_gemtext(filename)?;
+    let (meta, body) = Metadata::split(&Document::parse(&text));
+    Ok(meta
+        .title
+        .unwrap_or_else(|| String::from(body.first_heading().unwrap_or(default))))
+}
+
+/// Escape the XML special characters of a text.
+pub(crate) fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+/// A temporary directory for tests, named after the test and the
+/// process, and removed when dropped, even if the test panics.
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    /// Create the empty directory `gematom-{name}-{pid}` in the
+    /// temporary directory of the system.
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("gematom-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    /// Create the directory with the files at the relative `paths`,
+    /// and their parent directories, all holding `contents`.
+    pub fn with_files(name: &str, paths: &[&str], contents: &str) -> TempDir {
+        let dir = TempDir::new(name);
+        for path in paths {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_file() {
+        assert!(is_file("/etc/hosts"));
+        assert!(!is_file("/etc"));
+        assert!(!is_file("/dev/loop0"));
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(xml_escape("a < b & \"c\""), "a &lt; b &amp; &quot;c&quot;");
+    }
+
+    #[test]
+    fn test_write_if_changed() {
+        let dir = TempDir::new("write");
+        let path = dir.join("atom.xml");
+        assert!(write_if_changed(&path, b"spam", Access::Served, true).unwrap());
+        assert!(!write_if_changed(&path, b"spam", Access::Served, true).unwrap());
+        assert!(write_if_changed(&path, b"eggs", Access::Served, true).unwrap());
+        assert!(is_world_readable(path.to_str().unwrap()));
+        assert_eq!(fs::read(dir.join("atom.xml.bak")).unwrap(), b"spam");
+
+        let private = dir.join("state.json");
+        assert!(write_if_changed(&private, b"spam", Access::Private, false).unwrap());
+        assert!(!is_world_readable(private.to_str().unwrap()));
+
+        // the mode of an existing file is kept
+        fs::set_permissions(&private, fs::Permissions::from_mode(0o640)).unwrap();
+        assert!(write_if_changed(&private, b"eggs", Access::Default, false).unwrap());
+        let mode = fs::metadata(&private).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    fn test_is_world_readable() {
+        assert!(is_world_readable("/etc/hosts"));
+        assert!(!is_world_readable("/etc/shadow"));
+    }
+}
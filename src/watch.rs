@@ -0,0 +1,149 @@
+//! Watching the directories of a site for changes, with inotify.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use inotify::{Inotify, WatchDescriptor, WatchMask};
+
+use crate::error::{GemAtomError, Result};
+
+/// Events worth rebuilding the feeds for.
+fn mask() -> WatchMask {
+    WatchMask::CLOSE_WRITE
+        | WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+        | WatchMask::ATTRIB
+}
+
+/// Watches directories and their subdirectories.
+pub struct Watcher {
+    inotify: Inotify,
+    dirs: Vec<PathBuf>,
+    /// Files whose changes are not reported, e.g. generated files.
+    ignored: Vec<PathBuf>,
+    watched: HashMap<WatchDescriptor, PathBuf>,
+    buffer: Vec<u8>,
+}
+
+impl Watcher {
+    /// Watch `dirs`, ignoring changes of hidden files and of the files
+    /// at the paths `ignored`.
+    pub fn new(dirs: Vec<PathBuf>, ignored: Vec<PathBuf>) -> Result<Watcher> {
+        let inotify = Inotify::init().map_err(|e| GemAtomError::io(dirs_path(&dirs), e))?;
+        Ok(Watcher {
+            inotify,
+            dirs,
+            ignored,
+            watched: HashMap::new(),
+            buffer: vec![0; 4096],
+        })
+    }
+
+    /// Wait for changes, until none happened for `debounce`. Returns
+    /// the changed paths.
+    pub fn wait(&mut self, debounce: Duration) -> Result<BTreeSet<PathBuf>> {
+        // directories may have been created since the last call
+        for dir in self.dirs.clone() {
+            self.add_watches(&dir)?;
+        }
+        let mut changed = BTreeSet::new();
+        while changed.is_empty() {
+            self.read(true, &mut changed)?;
+        }
+        loop {
+            thread::sleep(debounce);
+            if !self.read(false, &mut changed)? {
+                return Ok(changed);
+            }
+        }
+    }
+
+    /// Watch `dir` and its subdirectories. Missing directories are
+    /// skipped.
+    fn add_watches(&mut self, dir: &Path) -> Result<()> {
+        let wd = match self.inotify.add_watch(dir, mask()) {
+            Ok(wd) => wd,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(GemAtomError::io(dir, e)),
+        };
+        self.watched.insert(wd, dir.to_path_buf());
+        let entries = fs::read_dir(dir).map_err(|e| GemAtomError::io(dir, e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| GemAtomError::io(dir, e))?.path();
+            if path.is_dir() {
+                self.add_watches(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the pending events, waiting for some if `block` is set,
+    /// and add the changed paths to `changed`. Returns whether any
+    /// event was read, ignored or not.
+    fn read(&mut self, block: bool, changed: &mut BTreeSet<PathBuf>) -> Result<bool> {
+        let dirs = &self.dirs;
+        let events = if block {
+            self.inotify.read_events_blocking(&mut self.buffer)
+        } else {
+            self.inotify.read_events(&mut self.buffer)
+        };
+        let events = events.map_err(|e| GemAtomError::io(dirs_path(dirs), e))?;
+        let mut any = false;
+        for event in events {
+            any = true;
+            let (dir, name) = match (self.watched.get(&event.wd), event.name) {
+                (Some(dir), Some(name)) => (dir, name),
+                _ => continue,
+            };
+            // hidden files are temporary files, editor backups, or
+            // the state file
+            let hidden = name.to_string_lossy().starts_with('.');
+            let path = dir.join(name);
+            if hidden || self.ignored.contains(&path) {
+                continue;
+            }
+            changed.insert(path);
+        }
+        Ok(any)
+    }
+}
+
+/// Path to report inotify errors against.
+fn dirs_path(dirs: &[PathBuf]) -> PathBuf {
+    dirs.first().cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::TempDir;
+
+    #[test]
+    fn test_wait() {
+        let root = TempDir::new("watch");
+        fs::create_dir_all(root.join("spam")).unwrap();
+        let mut watcher =
+            Watcher::new(vec![root.to_path_buf()], vec![root.join("atom.xml")]).unwrap();
+        let writer = {
+            let root = root.to_path_buf();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                fs::write(root.join("atom.xml"), "ignored").unwrap();
+                fs::write(root.join(".eggs.gmi.swp"), "ignored").unwrap();
+                fs::write(root.join("spam/eggs.gmi"), "# Eggs\n").unwrap();
+            })
+        };
+        let changed = watcher.wait(Duration::from_millis(100)).unwrap();
+        writer.join().unwrap();
+        assert_eq!(
+            changed.into_iter().collect::<Vec<_>>(),
+            vec![root.join("spam/eggs.gmi")]
+        );
+    }
+}